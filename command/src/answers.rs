@@ -0,0 +1,125 @@
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::{
+    error::{Error, Result},
+    CheckoutAction, Setting, SetupMethod,
+};
+
+/// Pre-filled responses for every prompt in `setup`, loaded from a TOML file
+/// so the wizard can run unattended (CI runners, provisioning scripts, etc).
+///
+/// ```toml
+/// setup_method = "advanced"
+/// continue_without_git_lfs = true
+/// services = ["email", "search"]
+/// continue_over_budget = true
+/// options = ["setup-database", "enable-rate-limiting"]
+/// su_password = "admin-secret"
+/// password = "user-secret"
+/// setup_api_tokens = true
+/// broken_checkouts = "reclone"
+/// remap_ports = true
+/// ```
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Answers {
+    pub setup_method: Option<SetupMethod>,
+    pub continue_without_git_lfs: Option<bool>,
+    pub services: Option<Vec<String>>,
+    pub continue_over_budget: Option<bool>,
+    pub options: Option<Vec<Setting>>,
+    pub su_password: Option<String>,
+    pub password: Option<String>,
+    pub setup_api_tokens: Option<bool>,
+    /// What to do with each repository directory that holds a broken or unexpected checkout
+    pub broken_checkouts: Option<CheckoutAction>,
+    /// Whether to publish services on other host ports when theirs are in use
+    pub remap_ports: Option<bool>,
+}
+
+impl Answers {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path).map_err(|err| {
+            Error::Config(format!(
+                "Could not read answers file {}: {err}",
+                path.display()
+            ))
+        })?;
+
+        Self::parse(&contents)
+            .map_err(|err| Error::Config(format!("Invalid answers file {}: {err}", path.display())))
+    }
+
+    fn parse(contents: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(contents)
+    }
+
+    /// Returns the answer for `key`, or an error naming the missing key
+    /// so the caller knows exactly what to add to the answers file.
+    pub fn require<T: Clone>(key: &str, value: Option<&T>) -> Result<T> {
+        value
+            .cloned()
+            .ok_or_else(|| Error::Config(format!("Missing answer for `{key}` in the answers file")))
+    }
+
+    pub fn invalid(key: &str, reason: &str) -> Error {
+        Error::Config(format!(
+            "Invalid answer for `{key}` in the answers file: {reason}"
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_full_answers() {
+        let answers = Answers::parse(
+            r#"
+            setup_method = "advanced"
+            continue_without_git_lfs = false
+            services = ["email", "search"]
+            options = ["setup-database", "enable-rate-limiting"]
+            su_password = "foo"
+            password = "bar"
+            setup_api_tokens = true
+            remap_ports = false
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(answers.setup_method, Some(SetupMethod::Advanced));
+        assert_eq!(answers.continue_without_git_lfs, Some(false));
+        assert_eq!(
+            answers.services,
+            Some(vec!["email".to_string(), "search".to_string()])
+        );
+        assert_eq!(
+            answers.options,
+            Some(vec![Setting::SetupDatabase, Setting::EnableRateLimiting])
+        );
+        assert_eq!(answers.su_password.as_deref(), Some("foo"));
+        assert_eq!(answers.password.as_deref(), Some("bar"));
+        assert_eq!(answers.setup_api_tokens, Some(true));
+        assert_eq!(answers.remap_ports, Some(false));
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_keys() {
+        assert!(Answers::parse("setup_mode = \"quick\"").is_err());
+        assert!(Answers::parse("setup_method = \"fast\"").is_err());
+    }
+
+    #[test]
+    fn test_require_names_missing_key() {
+        let answers = Answers::default();
+        let err = Answers::require("password", answers.password.as_ref()).unwrap_err();
+        assert!(matches!(err, Error::Config(_)));
+        assert_eq!(
+            err.to_string(),
+            "Missing answer for `password` in the answers file"
+        );
+    }
+}
@@ -0,0 +1,539 @@
+//! `compose.override.yml`, which Docker Compose merges into `compose.yml` automatically.
+//! It is regenerated from `Config` whenever the settings are saved, so that worktrees,
+//! port remaps, extra volumes, environment overrides and resource limits need no
+//! changes to the compose files themselves.
+
+use std::{collections::BTreeMap, fmt::Write as _, path::Path};
+
+use crate::{
+    error::{Error, Result},
+    Config,
+};
+
+pub const OVERRIDE_FILE: &str = "compose.override.yml";
+
+/// The first line of every generated override, so a hand-written one is never overwritten
+const HEADER: &str = "# Generated by lila-docker from settings.toml, do not edit";
+
+/// A `./repos/<project>` bind mount of a compose service
+#[derive(Clone, Eq, PartialEq, Debug)]
+struct RepoMount {
+    project: String,
+    /// The part of the source after `./repos/<project>`, e.g. `/public`
+    subpath: String,
+    /// Everything after the source, e.g. `/lila/public` or `/lila:ro`
+    target: String,
+}
+
+/// The parts of a compose service that the override can change
+#[derive(Clone, Default, Eq, PartialEq, Debug)]
+struct Service {
+    name: String,
+    /// Published ports as written, e.g. `8080:80`
+    ports: Vec<String>,
+    repo_mounts: Vec<RepoMount>,
+    profiles: Vec<String>,
+}
+
+/// Read the services of a compose file written in this repo's style:
+/// services indented by two spaces, their keys by four, and ports,
+/// volumes and profiles listed in short syntax.
+fn services(compose: &str) -> Vec<Service> {
+    let mut services: Vec<Service> = vec![];
+    let mut in_services = false;
+    let mut key = "";
+
+    for line in compose.lines() {
+        if !line.starts_with(' ') && !line.trim().is_empty() {
+            in_services = line.trim_end() == "services:";
+            continue;
+        }
+        if !in_services {
+            continue;
+        }
+        if let Some(name) = line
+            .strip_prefix("  ")
+            .filter(|rest| !rest.starts_with(' '))
+            .and_then(|rest| rest.trim_end().strip_suffix(':'))
+        {
+            services.push(Service {
+                name: name.to_string(),
+                ..Default::default()
+            });
+            key = "";
+            continue;
+        }
+        let Some(service) = services.last_mut() else {
+            continue;
+        };
+        if let Some(rest) = line
+            .strip_prefix("    ")
+            .filter(|rest| !rest.starts_with(' '))
+        {
+            key = rest.split(':').next().unwrap_or_default();
+            continue;
+        }
+        let Some(item) = line.trim().strip_prefix("- ") else {
+            continue;
+        };
+        let item = item.trim_matches('"');
+        match key {
+            "ports" => service.ports.push(item.to_string()),
+            "profiles" => service.profiles.push(item.to_string()),
+            "volumes" => {
+                let Some((source, target)) = item
+                    .strip_prefix("./repos/")
+                    .and_then(|volume| volume.split_once(':'))
+                else {
+                    continue;
+                };
+                let (project, subpath) = source
+                    .find('/')
+                    .map_or((source, ""), |slash| source.split_at(slash));
+                service.repo_mounts.push(RepoMount {
+                    project: project.to_string(),
+                    subpath: subpath.to_string(),
+                    target: target.to_string(),
+                });
+            }
+            _ => {}
+        }
+    }
+    services
+}
+
+/// A path from `settings.toml` in the form compose expects for a bind mount source
+fn mount_source(path: &str) -> String {
+    let path = path.trim_end_matches('/');
+    if path.starts_with('/') || path.starts_with("./") || path.starts_with("../") {
+        path.to_string()
+    } else {
+        format!("./{path}")
+    }
+}
+
+/// `port` (`[ip:]host:container[/protocol]`) published on `host_port` instead
+fn remap_port(port: &str, remaps: &BTreeMap<String, u16>) -> String {
+    let (published, container) = port.rsplit_once(':').unwrap_or(("", port));
+    let target = container.split('/').next().unwrap_or(container);
+    let Some(host_port) = remaps.get(target) else {
+        return port.to_string();
+    };
+    match published.rsplit_once(':') {
+        Some((ip, _)) => format!("{ip}:{host_port}:{container}"),
+        None => format!("{host_port}:{container}"),
+    }
+}
+
+/// The container port of `port`, without its protocol
+fn container_port(port: &str) -> &str {
+    let container = port.rsplit(':').next().unwrap_or(port);
+    container.split('/').next().unwrap_or(container)
+}
+
+/// The host port of `port`, `None` when docker picks one
+fn host_port(port: &str) -> Option<u16> {
+    let mut parts = port.rsplit(':');
+    parts.next();
+    parts.next()?.parse().ok()
+}
+
+/// The ports of `service` with `remaps` applied, including container ports
+/// that the compose files do not publish at all.
+fn remapped_ports(service: &Service, remaps: &BTreeMap<String, u16>) -> Vec<String> {
+    let mut ports: Vec<String> = service
+        .ports
+        .iter()
+        .map(|port| remap_port(port, remaps))
+        .collect();
+    for (container, host) in remaps {
+        if !service
+            .ports
+            .iter()
+            .any(|port| container_port(port) == container)
+        {
+            ports.push(format!("{host}:{container}"));
+        }
+    }
+    ports
+}
+
+/// A host port that a running service publishes
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct PublishedPort {
+    pub service: String,
+    pub container: String,
+    pub host: u16,
+    /// The host port in the compose files, before any remap in `settings.toml`
+    pub default_host: u16,
+}
+
+/// The host ports published by the services that `profiles` enable, with the remaps of `config`
+fn published(config: &Config, compose_files: &[String], profiles: &[String]) -> Vec<PublishedPort> {
+    let mut published = vec![];
+    for service in compose_files.iter().flat_map(|file| services(file)) {
+        if !service.profiles.is_empty()
+            && !service
+                .profiles
+                .iter()
+                .any(|profile| profiles.contains(profile))
+        {
+            continue;
+        }
+        let remaps = config
+            .service(&service.name)
+            .and_then(|settings| settings.ports.clone())
+            .unwrap_or_default();
+        for port in remapped_ports(&service, &remaps) {
+            let Some(host) = host_port(&port) else {
+                continue;
+            };
+            let container = container_port(&port).to_string();
+            let default_host = service
+                .ports
+                .iter()
+                .find(|original| container_port(original) == container)
+                .and_then(|original| host_port(original))
+                .unwrap_or(host);
+            published.push(PublishedPort {
+                service: service.name.clone(),
+                container,
+                host,
+                default_host,
+            });
+        }
+    }
+    published
+}
+
+/// The host ports published by the services in `config.compose_profiles`
+pub fn published_ports(config: &Config) -> Result<Vec<PublishedPort>> {
+    let files = read_compose_files(config)?;
+    Ok(published(
+        config,
+        &files,
+        config.compose_profiles.as_deref().unwrap_or_default(),
+    ))
+}
+
+/// A YAML scalar that is never read as a number, boolean or sexagesimal port
+fn quote(value: &str) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+/// What the override sets for one service
+#[derive(Default)]
+struct ServiceOverride {
+    ports: Vec<String>,
+    volumes: Vec<String>,
+    environment: BTreeMap<String, String>,
+    cpus: Option<f64>,
+    memory: Option<String>,
+}
+
+impl ServiceOverride {
+    fn is_empty(&self) -> bool {
+        self.ports.is_empty()
+            && self.volumes.is_empty()
+            && self.environment.is_empty()
+            && self.cpus.is_none()
+            && self.memory.is_none()
+    }
+
+    fn write(&self, yaml: &mut String) {
+        if !self.ports.is_empty() {
+            // Compose appends ports from an override, so replace the whole list
+            yaml.push_str("    ports: !override\n");
+            for port in &self.ports {
+                let _ = writeln!(yaml, "      - {}", quote(port));
+            }
+        }
+        if !self.volumes.is_empty() {
+            yaml.push_str("    volumes:\n");
+            for volume in &self.volumes {
+                let _ = writeln!(yaml, "      - {}", quote(volume));
+            }
+        }
+        if !self.environment.is_empty() {
+            yaml.push_str("    environment:\n");
+            for (name, value) in &self.environment {
+                let _ = writeln!(yaml, "      {name}: {}", quote(value));
+            }
+        }
+        if let Some(cpus) = self.cpus {
+            let _ = writeln!(yaml, "    cpus: {cpus}");
+        }
+        if let Some(memory) = &self.memory {
+            let _ = writeln!(yaml, "    mem_limit: {}", quote(memory));
+        }
+    }
+}
+
+/// The override for `config`, given the contents of the compose files it uses.
+/// `None` when nothing needs to be overridden.
+fn render(config: &Config, compose_files: &[String]) -> Result<Option<String>> {
+    let services: Vec<Service> = compose_files
+        .iter()
+        .flat_map(|file| services(file))
+        .collect();
+
+    if let Some(unknown) = config
+        .services
+        .iter()
+        .flatten()
+        .map(|(name, _)| name)
+        .find(|name| !services.iter().any(|service| service.name == **name))
+    {
+        let mut known: Vec<&str> = services
+            .iter()
+            .map(|service| service.name.as_str())
+            .collect();
+        known.sort_unstable();
+        return Err(Error::Config(format!(
+            "`services.{unknown}` is not a service in the compose files, expected one of: {}",
+            known.join(", ")
+        )));
+    }
+
+    let mut overrides: BTreeMap<&str, ServiceOverride> = BTreeMap::new();
+    for service in &services {
+        let settings = config.service(&service.name).cloned().unwrap_or_default();
+        let mut service_override = ServiceOverride::default();
+
+        if let Some(remaps) = settings.ports.filter(|remaps| !remaps.is_empty()) {
+            service_override.ports = remapped_ports(service, &remaps);
+        }
+        for mount in &service.repo_mounts {
+            if let Some(worktree) = config.worktree(&mount.project) {
+                service_override.volumes.push(format!(
+                    "{}{}:{}",
+                    mount_source(worktree),
+                    mount.subpath,
+                    mount.target
+                ));
+            }
+        }
+        service_override
+            .volumes
+            .extend(settings.volumes.into_iter().flatten());
+        service_override.environment = settings.environment.unwrap_or_default();
+        service_override.cpus = settings.cpus;
+        service_override.memory = settings.memory;
+
+        if !service_override.is_empty() {
+            overrides.insert(&service.name, service_override);
+        }
+    }
+    if overrides.is_empty() {
+        return Ok(None);
+    }
+
+    let mut yaml = format!("{HEADER}\n---\nservices:\n");
+    for (name, service_override) in overrides {
+        let _ = writeln!(yaml, "  {name}:");
+        service_override.write(&mut yaml);
+    }
+    Ok(Some(yaml))
+}
+
+/// The compose files that `compose.yml` uses with `config`
+fn compose_files(config: &Config) -> Vec<String> {
+    vec![
+        "compose.yml".to_string(),
+        format!(
+            "compose-lila-ws-{}.yml",
+            config.lila_ws_container.as_deref().unwrap_or("image")
+        ),
+        "compose-search.yml".to_string(),
+    ]
+}
+
+/// The contents of `compose.override.yml` for `config`, `None` when there should be none
+pub fn render_override(config: &Config) -> Result<Option<String>> {
+    render(config, &read_compose_files(config)?)
+}
+
+fn read_compose_files(config: &Config) -> Result<Vec<String>> {
+    Ok(compose_files(config)
+        .iter()
+        .map(std::fs::read_to_string)
+        .collect::<std::io::Result<Vec<_>>>()?)
+}
+
+/// Write (or remove) `compose.override.yml` with the output of `render_override`.
+/// An override that lila-docker did not generate is left alone.
+pub fn write_override(contents: Option<&str>) -> Result {
+    let path = Path::new(OVERRIDE_FILE);
+    if let Ok(existing) = std::fs::read_to_string(path) {
+        if !existing.starts_with(HEADER) {
+            cliclack::log::warning(format!(
+                "Not updating {OVERRIDE_FILE} because it was not generated by lila-docker"
+            ))?;
+            return Ok(());
+        }
+    }
+
+    match contents {
+        Some(yaml) => std::fs::write(path, yaml)?,
+        None if path.exists() => std::fs::remove_file(path)?,
+        None => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RepositorySettings;
+
+    const COMPOSE: &str = "
+services:
+  lila:
+    working_dir: /lila
+    volumes:
+      - ./repos/lila:/lila
+      - ./repos/scalachess:/scalachess
+      - ./conf/lila.conf:/lila/conf/application.conf
+  nginx:
+    ports:
+      - 8080:80
+      - \"127.0.0.1:8443:443/tcp\"
+    volumes:
+      - ./repos/lila/public:/lila/public:ro
+volumes:
+  openingexplorer-data:
+";
+
+    #[test]
+    fn test_services() {
+        let services = services(COMPOSE);
+        assert_eq!(
+            services.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(),
+            ["lila", "nginx"]
+        );
+        assert_eq!(services[0].repo_mounts.len(), 2);
+        assert_eq!(services[1].ports, ["8080:80", "127.0.0.1:8443:443/tcp"]);
+        assert_eq!(
+            services[1].repo_mounts,
+            [RepoMount {
+                project: "lila".to_string(),
+                subpath: "/public".to_string(),
+                target: "/lila/public:ro".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_remap_port() {
+        let remaps = BTreeMap::from([("80".to_string(), 8081), ("443".to_string(), 9443)]);
+        assert_eq!(remap_port("8080:80", &remaps), "8081:80");
+        assert_eq!(
+            remap_port("127.0.0.1:8443:443/tcp", &remaps),
+            "127.0.0.1:9443:443/tcp"
+        );
+        assert_eq!(remap_port("9002:9002", &remaps), "9002:9002");
+    }
+
+    #[test]
+    fn test_published() {
+        let compose_files = ["compose.yml", "compose-lila-ws-build.yml"]
+            .map(|file| std::fs::read_to_string(format!("testdata/{file}")).unwrap());
+        let mut config = Config::default();
+        assert!(published(&config, &compose_files, &[]).is_empty());
+
+        let profiles = ["base".to_string(), "lila-ws-build".to_string()];
+        let port = |service: &str, container: &str, host, default_host| PublishedPort {
+            service: service.to_string(),
+            container: container.to_string(),
+            host,
+            default_host,
+        };
+        assert_eq!(
+            published(&config, &compose_files, &profiles),
+            [
+                port("caddy", "80", 8080, 8080),
+                port("lila_ws", "9664", 9664, 9664)
+            ]
+        );
+
+        config = toml::from_str(include_str!("../testdata/settings.toml")).unwrap();
+        assert_eq!(
+            published(&config, &compose_files, &profiles),
+            [
+                port("caddy", "80", 8081, 8080),
+                port("lila_ws", "9664", 19664, 9664),
+                port("lila_ws", "5005", 15005, 15005),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_worktree() {
+        let mut config = Config::default();
+        assert!(render(&config, &[COMPOSE.to_string()]).unwrap().is_none());
+
+        config.repositories = Some(BTreeMap::from([(
+            "lila".to_string(),
+            RepositorySettings {
+                worktree: Some("worktrees/lila/feature".to_string()),
+                ..Default::default()
+            },
+        )]));
+        assert_eq!(
+            render(&config, &[COMPOSE.to_string()]).unwrap().unwrap(),
+            format!(
+                "{HEADER}\n---\nservices:\n  lila:\n    volumes:\n      - \"./worktrees/lila/feature:/lila\"\n  nginx:\n    volumes:\n      - \"./worktrees/lila/feature/public:/lila/public:ro\"\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_render_unknown_service() {
+        let config: Config = toml::from_str("[services.nginxx]\ncpus = 1").unwrap();
+        let err = render(&config, &[COMPOSE.to_string()]).unwrap_err();
+        assert!(matches!(err, Error::Config(_)));
+        assert!(err.to_string().contains("lila, nginx"), "{err}");
+    }
+
+    /// Compare with `testdata/compose.override.yml`.
+    /// Run with `UPDATE_GOLDEN=1` to rewrite it after an intended change.
+    #[test]
+    fn test_render_golden() {
+        let config: Config = toml::from_str(include_str!("../testdata/settings.toml")).unwrap();
+        assert!(config.validate().is_ok());
+        let compose_files = ["compose.yml", "compose-lila-ws-build.yml"]
+            .map(|file| std::fs::read_to_string(format!("testdata/{file}")).unwrap());
+
+        let rendered = render(&config, &compose_files).unwrap().unwrap();
+        assert_eq!(
+            render(&config, &compose_files).unwrap().unwrap(),
+            rendered,
+            "the output should be deterministic"
+        );
+
+        let golden = "testdata/compose.override.yml";
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(golden, &rendered).unwrap();
+        }
+        assert_eq!(rendered, std::fs::read_to_string(golden).unwrap());
+    }
+
+    #[test]
+    fn test_compose_files_are_parsed() {
+        let compose = std::fs::read_to_string("../compose.yml").unwrap();
+        let services = services(&compose);
+        let projects: Vec<&str> = services
+            .iter()
+            .flat_map(|service| &service.repo_mounts)
+            .map(|mount| mount.project.as_str())
+            .collect();
+        for project in ["lila", "chessground", "lila-db-seed", "lila-push"] {
+            assert!(projects.contains(&project), "{project}");
+        }
+        let caddy = services
+            .iter()
+            .find(|service| service.name == "caddy")
+            .unwrap();
+        assert_eq!(caddy.ports, ["8080:80"]);
+    }
+}
@@ -0,0 +1,1467 @@
+use cliclack::log::{info, warning};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+use toml::{Table, Value};
+
+use crate::{
+    docker::Runtime,
+    error::{Error, Result},
+    Repository,
+};
+
+/// The host port of caddy (or mono in a Quick setup) in `compose.yml`
+pub const DEFAULT_LILA_PORT: u16 = 8080;
+
+/// Bumped whenever a field of `Config` is renamed, retyped or removed,
+/// together with a new entry in `MIGRATIONS`.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// `MIGRATIONS[n]` upgrades a `settings.toml` table from version `n` to `n + 1`.
+/// Files written before versioning was introduced have no `schema_version` and are version 0.
+const MIGRATIONS: [fn(&mut Table); SCHEMA_VERSION as usize] = [
+    // 0 -> 1: `schema_version` was added, no other field changed
+    |_| {},
+];
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Config {
+    pub schema_version: Option<u32>,
+    pub quick_setup: Option<bool>,
+    pub compose_profiles: Option<Vec<String>>,
+    pub lila_ws_container: Option<String>,
+    pub setup_database: Option<bool>,
+    pub setup_bbppairings: Option<bool>,
+    pub mock_email: Option<bool>,
+    pub enable_monitoring: Option<bool>,
+    pub enable_rate_limiting: Option<bool>,
+    pub su_password: Option<String>,
+    pub password: Option<String>,
+    pub setup_api_tokens: Option<bool>,
+    pub lila_domain: Option<String>,
+    pub lila_url: Option<String>,
+    /// Detected by `./lila-docker start`, see `docker::Runtime`
+    pub docker_runtime: Option<Runtime>,
+    /// Keyed by project name, e.g. `[repositories.lila]`
+    pub repositories: Option<BTreeMap<String, RepositorySettings>>,
+    /// Keyed by compose service name, e.g. `[services.lila]`
+    pub services: Option<BTreeMap<String, ServiceSettings>>,
+}
+
+/// How to clone one repository, when it should not be a shallow clone of the upstream default branch.
+///
+/// ```toml
+/// [repositories.lila]
+/// fork = "https://github.com/<you>/lila"
+/// branch = "my-feature"
+/// depth = 50
+///
+/// [repositories.scalachess]
+/// full_history = true
+///
+/// [repositories.lila-ws]
+/// worktree = "worktrees/lila-ws/my-feature"
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct RepositorySettings {
+    pub fork: Option<String>,
+    pub branch: Option<String>,
+    pub depth: Option<u32>,
+    /// Clone without `--depth`, for `git blame`, bisecting, etc
+    pub full_history: Option<bool>,
+    /// A `git worktree` of the clone to mount into the containers instead of `repos/<project>`
+    pub worktree: Option<String>,
+}
+
+/// Changes to one compose service, written to `compose.override.yml` instead of editing `compose.yml`.
+///
+/// ```toml
+/// [services.lila]
+/// environment = { JAVA_OPTS = "-Xmx6g" }
+/// cpus = 4
+/// memory = "8g"
+///
+/// [services.caddy]
+/// ports = { 80 = 8081 }  # publish container port 80 on host port 8081 instead of 8080
+/// volumes = ["~/lichess-assets:/assets:ro"]
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ServiceSettings {
+    /// Host port to publish each container port on, keyed by container port
+    pub ports: Option<BTreeMap<String, u16>>,
+    /// Mounted in addition to the volumes in the compose files
+    pub volumes: Option<Vec<String>>,
+    /// Set in addition to, or instead of, the environment in the compose files
+    pub environment: Option<BTreeMap<String, String>>,
+    pub cpus: Option<f64>,
+    /// A byte size such as `512m` or `4g`
+    pub memory: Option<String>,
+}
+
+/// A problem with a single `Config` field, and how to fix it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ValidationError {
+    pub field: &'static str,
+    pub message: String,
+    pub fix: String,
+}
+
+impl ValidationError {
+    fn new(field: &'static str, message: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            field,
+            message: message.into(),
+            fix: fix.into(),
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}`: {}\n  ↳ {}", self.field, self.message, self.fix)
+    }
+}
+
+/// Check a password can be passed through the environment, for use while prompting.
+/// Everything else is quoted safely by `to_env`.
+pub fn check_password(password: &str) -> Result<(), String> {
+    if password.contains('\0') {
+        Err("Passwords cannot contain NUL characters".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+/// The `host[:port]` part of a URL, if it has an http(s) scheme.
+fn url_authority(url: &str) -> Option<&str> {
+    let rest = url
+        .strip_prefix("http://")
+        .or_else(|| url.strip_prefix("https://"))?;
+    Some(rest.split(['/', '?', '#']).next().unwrap_or_default())
+}
+
+/// Whether `size` is a byte size that compose accepts, e.g. `1024`, `512m` or `4gb`
+fn is_byte_size(size: &str) -> bool {
+    let size = size.to_ascii_lowercase();
+    let unit = size.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+    let number = &size[..size.len() - unit.len()];
+    number.parse::<f64>().is_ok_and(|number| number > 0.0)
+        && ["", "b", "k", "kb", "m", "mb", "g", "gb"].contains(&unit)
+}
+
+/// The ways a command-line string can be read as a TOML value, most specific first.
+fn candidate_values(raw: &str) -> Vec<Value> {
+    let mut candidates = vec![];
+    if let Ok(mut table) = format!("value = {raw}").parse::<Table>() {
+        if let Some(value) = table.remove("value") {
+            candidates.push(value);
+        }
+    }
+    candidates.push(Value::String(raw.to_string()));
+    candidates.push(Value::Array(
+        raw.split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| Value::String(item.to_string()))
+            .collect(),
+    ));
+    candidates
+}
+
+/// Render a setting the way it would be typed on the command line.
+pub fn display_value(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        Value::Array(items) => items
+            .iter()
+            .map(display_value)
+            .collect::<Vec<_>>()
+            .join(","),
+        other => other.to_string(),
+    }
+}
+
+/// Every leaf value of `table` keyed by its dotted path, e.g. `repositories.lila.fork`.
+fn flatten(prefix: &str, table: Table, entries: &mut Vec<(String, Value)>) {
+    for (key, value) in table {
+        let key = if prefix.is_empty() {
+            key
+        } else {
+            format!("{prefix}.{key}")
+        };
+        match value {
+            Value::Table(table) => flatten(&key, table, entries),
+            value => entries.push((key, value)),
+        }
+    }
+}
+
+fn lookup<'a>(table: &'a Table, key: &str) -> Option<&'a Value> {
+    let (first, rest) = key.split_once('.').unwrap_or((key, ""));
+    let value = table.get(first)?;
+    if rest.is_empty() {
+        Some(value)
+    } else {
+        lookup(value.as_table()?, rest)
+    }
+}
+
+/// Insert `value` at a dotted path, creating (or replacing) the tables along the way.
+fn insert_at(table: &mut Table, key: &str, value: Value) {
+    match key.split_once('.') {
+        None => {
+            table.insert(key.to_string(), value);
+        }
+        Some((first, rest)) => {
+            let entry = table
+                .entry(first)
+                .or_insert_with(|| Value::Table(Table::new()));
+            if !entry.is_table() {
+                *entry = Value::Table(Table::new());
+            }
+            if let Value::Table(inner) = entry {
+                insert_at(inner, rest, value);
+            }
+        }
+    }
+}
+
+/// Remove the value at a dotted path, along with any tables left empty by its removal.
+fn remove_at(table: &mut Table, key: &str) {
+    match key.split_once('.') {
+        None => {
+            table.remove(key);
+        }
+        Some((first, rest)) => {
+            if let Some(Value::Table(inner)) = table.get_mut(first) {
+                remove_at(inner, rest);
+                if inner.is_empty() {
+                    table.remove(first);
+                }
+            }
+        }
+    }
+}
+
+struct Parsed {
+    config: Config,
+    /// The schema version the file was upgraded from, if it was outdated
+    migrated_from: Option<u32>,
+    unknown_keys: Vec<String>,
+}
+
+/// Upgrade `table` to `SCHEMA_VERSION` in place, returning the version it started at
+/// if any migration was applied.
+fn migrate(table: &mut Table) -> Result<Option<u32>, String> {
+    let version = match table.get("schema_version") {
+        None => 0,
+        Some(value) => value
+            .as_integer()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| format!("`schema_version` must be a positive integer, found {value}"))?,
+    };
+
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "it uses schema version {version}, but this version of lila-docker only understands up to {SCHEMA_VERSION}. Update lila-docker with `git pull`"
+        ));
+    }
+    if version == SCHEMA_VERSION {
+        return Ok(None);
+    }
+
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(table);
+    }
+    table.insert(
+        "schema_version".to_string(),
+        Value::Integer(SCHEMA_VERSION.into()),
+    );
+
+    Ok(Some(version))
+}
+
+macro_rules! to_env {
+    ($name_opt:ident) => {
+        $name_opt
+            .clone()
+            .map(|v| crate::dotenv::line(&stringify!($name_opt).to_uppercase(), &v.to_string()))
+            .unwrap_or_default()
+    };
+    ($key:ident, $value:expr) => {
+        crate::dotenv::line(&stringify!($key).to_uppercase(), &$value)
+    };
+}
+
+impl Config {
+    pub const SETTINGS_TOML: &'static str = "settings.toml";
+    const SETTINGS_ENV: &'static str = "settings.env";
+
+    /// Load `settings.toml`, printing a warning for every validation error.
+    pub fn load() -> Result<Self> {
+        let config = Self::load_unvalidated()?;
+        if let Err(errors) = config.validate() {
+            for error in errors {
+                warning(format!("{} is invalid: {error}", Self::SETTINGS_TOML))?;
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn load_unvalidated() -> Result<Self> {
+        let path = Path::new(Self::SETTINGS_TOML);
+        let Ok(contents) = std::fs::read_to_string(path) else {
+            return Ok(Self::default());
+        };
+
+        let parsed = Self::parse(&contents).map_err(|err| {
+            Error::Config(format!(
+                "{} could not be read: {err}\nFix the file, or delete it and re-run `./lila-docker setup`",
+                path.display()
+            ))
+        })?;
+
+        for key in &parsed.unknown_keys {
+            warning(format!(
+                "Ignoring unknown setting `{key}` in {}",
+                path.display()
+            ))?;
+        }
+
+        if let Some(from) = parsed.migrated_from {
+            let backup = path.with_extension(format!("toml.v{from}.bak"));
+            std::fs::copy(path, &backup)?;
+            parsed.config.write()?;
+            info(format!(
+                "Upgraded {} from schema version {from} to {SCHEMA_VERSION} (backup saved to {})",
+                path.display(),
+                backup.display()
+            ))?;
+        }
+
+        Ok(parsed.config)
+    }
+
+    fn parse(contents: &str) -> Result<Parsed, String> {
+        let mut table: Table = contents
+            .parse()
+            .map_err(|err: toml::de::Error| err.to_string())?;
+        let migrated_from = migrate(&mut table)?;
+
+        // Deserialize from text rather than the table so errors point at the offending line
+        let migrated_contents;
+        let contents = if migrated_from.is_some() {
+            migrated_contents = table.to_string();
+            &migrated_contents
+        } else {
+            contents
+        };
+        let config: Self = toml::from_str(contents).map_err(|err| err.to_string())?;
+
+        let mut known = vec![];
+        flatten(
+            "",
+            Table::try_from(&config).map_err(|err| err.to_string())?,
+            &mut known,
+        );
+        let mut present = vec![];
+        flatten("", table, &mut present);
+        let unknown_keys = present
+            .into_iter()
+            .map(|(key, _)| key)
+            .filter(|key| !known.iter().any(|(known, _)| known == key))
+            .collect();
+
+        Ok(Parsed {
+            config,
+            migrated_from,
+            unknown_keys,
+        })
+    }
+
+    /// Validate and write both `settings.toml` and `settings.env`.
+    pub fn save(&self) -> Result {
+        if let Err(errors) = self.validate() {
+            return Err(Error::Config(format!(
+                "Refusing to save invalid settings:\n{}",
+                errors
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            )));
+        }
+        self.write()
+    }
+
+    /// Record the detected container runtime without validating or rewriting the other settings,
+    /// so that an invalid `settings.toml` does not stop `./lila-docker start` before the setup
+    /// that can fix it
+    pub fn save_docker_runtime(&mut self, runtime: Runtime) -> Result {
+        self.docker_runtime = Some(runtime);
+        let toml = std::fs::read_to_string(Self::SETTINGS_TOML).ok();
+        let env = std::fs::read_to_string(Self::SETTINGS_ENV).ok();
+        let (toml, env) = self.with_docker_runtime(toml.as_deref(), env.as_deref())?;
+        std::fs::write(Self::SETTINGS_TOML, toml)?;
+        std::fs::write(Self::SETTINGS_ENV, env)?;
+        Ok(())
+    }
+
+    /// `settings.toml` and `settings.env` with only `docker_runtime` changed,
+    /// or written from scratch when they do not exist yet
+    fn with_docker_runtime(
+        &self,
+        toml: Option<&str>,
+        env: Option<&str>,
+    ) -> Result<(String, String)> {
+        let runtime = self
+            .docker_runtime
+            .map(|runtime| runtime.to_string())
+            .unwrap_or_default();
+        let toml = match toml {
+            Some(contents) => {
+                let mut table: Table = contents.parse().map_err(|err| {
+                    Error::Config(format!("{} could not be read: {err}", Self::SETTINGS_TOML))
+                })?;
+                insert_at(&mut table, "docker_runtime", Value::String(runtime.clone()));
+                toml::to_string(&table).expect("a parsed table always serializes to TOML")
+            }
+            None => self.to_toml(),
+        };
+        let env = match env {
+            Some(contents) => crate::dotenv::update(contents, &[("DOCKER_RUNTIME", &runtime)]),
+            None => self.to_env(),
+        };
+        Ok((toml, env))
+    }
+
+    fn write(&self) -> Result {
+        // Rendered first so that settings naming an unknown service are not saved
+        let compose_override = crate::compose::render_override(self)?;
+        std::fs::write(Self::SETTINGS_TOML, self.to_toml())?;
+        std::fs::write(Self::SETTINGS_ENV, self.to_env())?;
+        crate::compose::write_override(compose_override.as_deref())
+    }
+
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = vec![];
+
+        if let Some(url) = &self.lila_url {
+            match url_authority(url) {
+                None => errors.push(ValidationError::new(
+                    "lila_url",
+                    format!("`{url}` has no http:// or https:// scheme"),
+                    format!("use `http://{url}`"),
+                )),
+                Some("") => errors.push(ValidationError::new(
+                    "lila_url",
+                    format!("`{url}` has no hostname"),
+                    "run `./lila-docker hostname` to pick one",
+                )),
+                Some(authority) => {
+                    if let Some(domain) = self.lila_domain.as_ref().filter(|d| *d != authority) {
+                        errors.push(ValidationError::new(
+                            "lila_domain",
+                            format!("`{domain}` does not match the host of lila_url `{url}`"),
+                            format!(
+                                "set lila_domain to `{authority}`, or run `./lila-docker hostname`"
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+
+        match (&self.lila_domain, &self.lila_url) {
+            (Some(_), None) => errors.push(ValidationError::new(
+                "lila_url",
+                "is missing while lila_domain is set",
+                "run `./lila-docker hostname` to set both",
+            )),
+            (None, Some(_)) => errors.push(ValidationError::new(
+                "lila_domain",
+                "is missing while lila_url is set",
+                "run `./lila-docker hostname` to set both",
+            )),
+            _ => {}
+        }
+
+        if let Some(domain) = self.lila_domain.as_ref().filter(|d| d.contains("://")) {
+            errors.push(ValidationError::new(
+                "lila_domain",
+                format!("`{domain}` should not include a scheme"),
+                "use only `host:port`, e.g. `localhost:8080`",
+            ));
+        }
+
+        for (field, password) in [
+            ("su_password", &self.su_password),
+            ("password", &self.password),
+        ] {
+            if let Some(Err(reason)) = password.as_deref().map(check_password) {
+                errors.push(ValidationError::new(
+                    field,
+                    reason,
+                    "remove the NUL character from the password",
+                ));
+            }
+        }
+
+        if let Some(container) = self
+            .lila_ws_container
+            .as_ref()
+            .filter(|c| !["image", "build"].contains(&c.as_str()))
+        {
+            errors.push(ValidationError::new(
+                "lila_ws_container",
+                format!("`{container}` is not a lila-ws container type"),
+                "use `image` or `build`",
+            ));
+        }
+
+        if let Some(Err(problems)) = self
+            .compose_profiles
+            .as_deref()
+            .map(crate::services::validate_profiles)
+        {
+            errors.extend(problems.into_iter().map(|problem| {
+                ValidationError::new(
+                    "compose_profiles",
+                    problem,
+                    "run `./lila-docker add-services`, or re-run the setup",
+                )
+            }));
+        }
+
+        errors.extend(self.validate_repositories());
+        errors.extend(self.validate_services());
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn validate_repositories(&self) -> Vec<ValidationError> {
+        let projects: Vec<String> = crate::services::all_repositories()
+            .into_iter()
+            .map(|repo| repo.project)
+            .collect();
+        let mut errors = vec![];
+
+        for (project, settings) in self.repositories.iter().flatten() {
+            if !projects.contains(project) {
+                errors.push(ValidationError::new(
+                    "repositories",
+                    format!("`{project}` is not a repository lila-docker clones"),
+                    format!("use one of {}", projects.join(", ")),
+                ));
+            }
+            if settings
+                .fork
+                .as_ref()
+                .is_some_and(|fork| fork.trim().is_empty())
+            {
+                errors.push(ValidationError::new(
+                    "repositories",
+                    format!("`{project}` has an empty fork URL"),
+                    format!(
+                        "set it to the URL of your fork, e.g. `https://github.com/<you>/{project}`"
+                    ),
+                ));
+            }
+            if let Some(branch) = settings
+                .branch
+                .as_ref()
+                .filter(|b| b.is_empty() || b.starts_with('-') || b.contains(char::is_whitespace))
+            {
+                errors.push(ValidationError::new(
+                    "repositories",
+                    format!("`{project}` has an invalid branch name `{branch}`"),
+                    "use the name of a branch on the fork, or upstream if there is no fork",
+                ));
+            }
+            if settings.depth == Some(0) {
+                errors.push(ValidationError::new(
+                    "repositories",
+                    format!("`{project}` has a clone depth of 0"),
+                    format!(
+                        "use a depth of 1 or more, or remove it to use the default of {}",
+                        crate::repository::DEFAULT_DEPTH
+                    ),
+                ));
+            }
+            if settings
+                .worktree
+                .as_ref()
+                .is_some_and(|worktree| worktree.trim().is_empty())
+            {
+                errors.push(ValidationError::new(
+                    "repositories",
+                    format!("`{project}` has an empty worktree path"),
+                    format!("run `./lila-docker worktree use {project} main` to mount repos/{project} again"),
+                ));
+            }
+            if let (Some(depth), Some(true)) = (settings.depth, settings.full_history) {
+                errors.push(ValidationError::new(
+                    "repositories",
+                    format!("`{project}` sets both a clone depth of {depth} and full_history"),
+                    format!("unset `repositories.{project}.depth` or `repositories.{project}.full_history`"),
+                ));
+            }
+        }
+
+        errors
+    }
+
+    fn validate_services(&self) -> Vec<ValidationError> {
+        let mut errors = vec![];
+
+        for (service, settings) in self.services.iter().flatten() {
+            for (container, host) in settings.ports.iter().flatten() {
+                if container.parse::<u16>().map_or(true, |port| port == 0) {
+                    errors.push(ValidationError::new(
+                        "services",
+                        format!("`{service}` remaps `{container}`, which is not a port number"),
+                        format!(
+                            "key the ports by the container port, e.g. `ports = {{ 80 = {host} }}`"
+                        ),
+                    ));
+                }
+                if *host == 0 {
+                    errors.push(ValidationError::new(
+                        "services",
+                        format!("`{service}` publishes port {container} on host port 0"),
+                        "use a host port between 1 and 65535",
+                    ));
+                }
+            }
+            for volume in settings.volumes.iter().flatten() {
+                if !volume.contains(':') {
+                    errors.push(ValidationError::new(
+                        "services",
+                        format!("`{service}` has a volume `{volume}` without a container path"),
+                        "use `<host path>:<container path>`, optionally followed by `:ro`",
+                    ));
+                }
+            }
+            for name in settings.environment.iter().flatten().map(|(name, _)| name) {
+                if name.is_empty()
+                    || name.contains(['=', '\0'])
+                    || name.contains(char::is_whitespace)
+                {
+                    errors.push(ValidationError::new(
+                        "services",
+                        format!("`{service}` sets an invalid environment variable `{name}`"),
+                        "use a name without spaces or `=`",
+                    ));
+                }
+            }
+            if let Some(cpus) = settings
+                .cpus
+                .filter(|cpus| !cpus.is_finite() || *cpus <= 0.0)
+            {
+                errors.push(ValidationError::new(
+                    "services",
+                    format!("`{service}` is limited to {cpus} CPUs"),
+                    "use a positive number such as `2` or `0.5`",
+                ));
+            }
+            if let Some(memory) = settings
+                .memory
+                .as_ref()
+                .filter(|memory| !is_byte_size(memory))
+            {
+                errors.push(ValidationError::new(
+                    "services",
+                    format!("`{service}` has an invalid memory limit `{memory}`"),
+                    "use a byte size such as `512m` or `4g`",
+                ));
+            }
+        }
+
+        errors
+    }
+
+    /// The host port that lila is served on, from `lila_domain`
+    pub fn lila_port(&self) -> u16 {
+        self.lila_domain
+            .as_deref()
+            .and_then(|domain| domain.rsplit_once(':'))
+            .and_then(|(_, port)| port.parse().ok())
+            .unwrap_or(DEFAULT_LILA_PORT)
+    }
+
+    /// Serve lila at `hostname`, on the port it is published on
+    pub fn set_hostname(&mut self, hostname: &str) {
+        let port = self.lila_port();
+        self.lila_domain = Some(format!("{hostname}:{port}"));
+        self.lila_url = Some(format!("http://{hostname}:{port}"));
+    }
+
+    /// Point `lila_domain` and `lila_url` at host port `to` if they use port `from`
+    pub fn remap_lila_port(&mut self, from: u16, to: u16) {
+        if self.lila_port() != from {
+            return;
+        }
+        let domain = self
+            .lila_domain
+            .clone()
+            .unwrap_or_else(|| format!("localhost:{DEFAULT_LILA_PORT}"));
+        let host = domain
+            .rsplit_once(':')
+            .map_or(domain.as_str(), |(host, _)| host);
+        let scheme = self
+            .lila_url
+            .as_deref()
+            .and_then(|url| url.split_once("://"))
+            .map_or("http", |(scheme, _)| scheme);
+        self.lila_url = Some(format!("{scheme}://{host}:{to}"));
+        self.lila_domain = Some(format!("{host}:{to}"));
+    }
+
+    /// The settings of compose service `service`, if it has any
+    pub fn service(&self, service: &str) -> Option<&ServiceSettings> {
+        self.services.as_ref()?.get(service)
+    }
+
+    /// `repo` with the fork, branch, depth (or full history) and worktree from its `[repositories.<project>]` table applied.
+    pub fn repository(&self, repo: &Repository) -> Repository {
+        let mut repo = repo.clone();
+        if let Some(settings) = self
+            .repositories
+            .as_ref()
+            .and_then(|repositories| repositories.get(&repo.project))
+        {
+            repo.fork = settings.fork.clone().or(repo.fork);
+            repo.branch = settings.branch.clone().or(repo.branch);
+            repo.depth = if settings.full_history == Some(true) {
+                None
+            } else {
+                settings.depth.or(repo.depth)
+            };
+            repo.worktree = settings.worktree.as_ref().map(PathBuf::from);
+        }
+        repo
+    }
+
+    /// The worktree mounted in place of `repos/<project>`, if it is not the clone itself
+    pub fn worktree(&self, project: &str) -> Option<&str> {
+        self.repositories
+            .as_ref()?
+            .get(project)?
+            .worktree
+            .as_deref()
+    }
+
+    /// Every value that is set, keyed by its dotted path and sorted, excluding `schema_version`.
+    pub fn entries(&self) -> Vec<(String, Value)> {
+        let mut entries = vec![];
+        flatten("", self.to_table(), &mut entries);
+        entries
+            .into_iter()
+            .filter(|(key, _)| key != "schema_version")
+            .collect()
+    }
+
+    /// `key` may be a dotted path into a table, e.g. `repositories.lila.fork`.
+    pub fn get(&self, key: &str) -> Result<Option<Value>, String> {
+        Self::check_key(key)?;
+        Ok(lookup(&self.to_table(), key).cloned())
+    }
+
+    /// Set `key` from a command-line string, interpreting it as whatever type the field has:
+    /// a boolean, a comma-separated list, or plain text.
+    pub fn set(&mut self, key: &str, raw: &str) -> Result<(), String> {
+        Self::check_key(key)?;
+
+        let mut last_error = String::new();
+        for candidate in candidate_values(raw) {
+            let mut table = self.to_table();
+            insert_at(&mut table, key, candidate);
+            match Value::Table(table).try_into::<Self>() {
+                Ok(config) => {
+                    *self = config;
+                    return Ok(());
+                }
+                Err(err) => last_error = err.message().to_string(),
+            }
+        }
+        Err(format!(
+            "`{raw}` is not a valid value for `{key}`: {last_error}"
+        ))
+    }
+
+    pub fn unset(&mut self, key: &str) -> Result<(), String> {
+        Self::check_key(key)?;
+
+        let mut table = self.to_table();
+        remove_at(&mut table, key);
+        *self = Value::Table(table)
+            .try_into()
+            .map_err(|err: toml::de::Error| err.message().to_string())?;
+        Ok(())
+    }
+
+    fn to_table(&self) -> Table {
+        Table::try_from(self).expect("Config always serializes to a table")
+    }
+
+    /// Settable keys are the fields that survive a round trip through `Config`;
+    /// unknown keys are silently dropped by deserialization.
+    fn check_key(key: &str) -> Result<(), String> {
+        if key == "schema_version" {
+            return Err("`schema_version` is managed by lila-docker".to_string());
+        }
+
+        let known = [
+            Value::Boolean(true),
+            Value::String(String::new()),
+            Value::Integer(1),
+            Value::Array(vec![]),
+            Value::Table(Table::new()),
+        ]
+        .into_iter()
+        .any(|probe| {
+            let mut table = Table::new();
+            insert_at(&mut table, key, probe);
+            Value::Table(table)
+                .try_into::<Self>()
+                .is_ok_and(|config| lookup(&config.to_table(), key).is_some())
+        });
+
+        if known {
+            Ok(())
+        } else {
+            Err(format!(
+                "`{key}` is not a setting. Run `./lila-docker config list` to see the current settings"
+            ))
+        }
+    }
+
+    /// Check that `settings.env` still holds exactly what `to_env` would write,
+    /// i.e. it parses and was not edited by hand or left behind by an older version.
+    pub fn check_env_file(&self) -> Result<(), ValidationError> {
+        let Ok(contents) = std::fs::read_to_string(Self::SETTINGS_ENV) else {
+            return Ok(());
+        };
+        let fix = "re-run `./lila-docker setup`, or any command that saves the settings, to regenerate it";
+
+        let written = crate::dotenv::parse(&contents)
+            .map_err(|err| ValidationError::new(Self::SETTINGS_ENV, err, fix))?;
+        let expected = crate::dotenv::parse(&self.to_env())
+            .map_err(|err| ValidationError::new(Self::SETTINGS_ENV, err, fix))?;
+
+        if written == expected {
+            Ok(())
+        } else {
+            Err(ValidationError::new(
+                Self::SETTINGS_ENV,
+                format!("does not match {}", Self::SETTINGS_TOML),
+                fix,
+            ))
+        }
+    }
+
+    fn to_toml(&self) -> String {
+        let mut config = self.clone();
+        config.schema_version = Some(SCHEMA_VERSION);
+        toml::to_string(&config).expect("Config always serializes to TOML")
+    }
+
+    /// The variables of `settings.env`, for commands that run `docker compose` themselves.
+    pub fn env(&self) -> Vec<(String, String)> {
+        crate::dotenv::parse(&self.to_env()).expect("to_env output always parses")
+    }
+
+    fn to_env(&self) -> String {
+        let Self {
+            schema_version: _,
+            quick_setup,
+            compose_profiles,
+            lila_ws_container,
+            setup_database,
+            setup_bbppairings,
+            mock_email,
+            enable_monitoring,
+            enable_rate_limiting,
+            su_password,
+            password,
+            setup_api_tokens,
+            lila_domain,
+            lila_url,
+            docker_runtime,
+            repositories: _,
+            services: _,
+        } = self;
+        let compose_profiles_string = compose_profiles
+            .clone()
+            .map(|v| v.join(","))
+            .unwrap_or_default();
+
+        vec![
+            to_env!(quick_setup),
+            to_env!(compose_profiles, compose_profiles_string),
+            to_env!(lila_ws_container),
+            to_env!(setup_database),
+            to_env!(setup_bbppairings),
+            to_env!(mock_email),
+            to_env!(enable_monitoring),
+            to_env!(enable_rate_limiting),
+            to_env!(su_password),
+            to_env!(password),
+            to_env!(setup_api_tokens),
+            to_env!(lila_domain),
+            to_env!(lila_url),
+            to_env!(docker_runtime),
+        ]
+        .iter()
+        .filter(|line| !line.is_empty())
+        .map(std::string::ToString::to_string)
+        .collect::<Vec<String>>()
+        .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn valid_config() -> Config {
+        Config {
+            compose_profiles: Some(vec!["base".to_string(), "email".to_string()]),
+            lila_ws_container: Some("image".to_string()),
+            su_password: Some("password".to_string()),
+            password: Some("password".to_string()),
+            lila_domain: Some("localhost:8080".to_string()),
+            lila_url: Some("http://localhost:8080".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn invalid_fields(config: &Config) -> Vec<&'static str> {
+        config
+            .validate()
+            .err()
+            .unwrap_or_default()
+            .iter()
+            .map(|error| error.field)
+            .collect()
+    }
+
+    #[test]
+    fn test_validate_ok() {
+        assert!(valid_config().validate().is_ok());
+        assert!(Config::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_urls() {
+        let config = Config {
+            lila_url: Some("localhost:8080".to_string()),
+            ..valid_config()
+        };
+        assert_eq!(invalid_fields(&config), ["lila_url"]);
+
+        let config = Config {
+            lila_url: Some("https://example.com:8080/".to_string()),
+            ..valid_config()
+        };
+        let errors = config.validate().unwrap_err();
+        assert_eq!(errors[0].field, "lila_domain");
+        assert_eq!(
+            errors[0].fix,
+            "set lila_domain to `example.com:8080`, or run `./lila-docker hostname`"
+        );
+
+        let config = Config {
+            lila_domain: None,
+            ..valid_config()
+        };
+        assert_eq!(invalid_fields(&config), ["lila_domain"]);
+    }
+
+    #[test]
+    fn test_validate_passwords() {
+        let config = Config {
+            su_password: Some("pa\0ss".to_string()),
+            password: Some("\0".to_string()),
+            ..valid_config()
+        };
+        assert_eq!(invalid_fields(&config), ["su_password", "password"]);
+        assert_eq!(
+            check_password("a\0"),
+            Err("Passwords cannot contain NUL characters".to_string())
+        );
+        assert_eq!(check_password("correct horse $battery #staple"), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_profiles_and_container() {
+        let config = Config {
+            compose_profiles: Some(vec!["base".to_string(), "emial".to_string()]),
+            lila_ws_container: Some("source".to_string()),
+            ..valid_config()
+        };
+        assert_eq!(
+            invalid_fields(&config),
+            ["lila_ws_container", "compose_profiles"]
+        );
+    }
+
+    #[test]
+    fn test_validation_error_display() {
+        let error = ValidationError::new("lila_url", "is broken", "fix it");
+        assert_eq!(error.to_string(), "`lila_url`: is broken\n  ↳ fix it");
+    }
+
+    #[test]
+    fn test_parse_current_version() {
+        let parsed = Config::parse("schema_version = 1\nquick_setup = true\n").unwrap();
+        assert_eq!(parsed.config.quick_setup, Some(true));
+        assert_eq!(parsed.migrated_from, None);
+        assert!(parsed.unknown_keys.is_empty());
+    }
+
+    #[test]
+    fn test_parse_migrates_unversioned_file() {
+        let parsed = Config::parse("lila_domain = \"baz:8080\"\n").unwrap();
+        assert_eq!(parsed.migrated_from, Some(0));
+        assert_eq!(parsed.config.schema_version, Some(SCHEMA_VERSION));
+        assert_eq!(parsed.config.lila_domain.as_deref(), Some("baz:8080"));
+    }
+
+    #[test]
+    fn test_parse_reports_unknown_keys() {
+        let parsed =
+            Config::parse("schema_version = 1\nsetup_db = true\nmock_email = false\n").unwrap();
+        assert_eq!(parsed.unknown_keys, ["setup_db"]);
+        assert_eq!(parsed.config.mock_email, Some(false));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Config::parse("quick_setup = ").is_err());
+        let err = Config::parse("schema_version = 1\nquick_setup = \"yes\"\n")
+            .err()
+            .unwrap();
+        assert!(err.contains("line 2"));
+        assert!(err.contains("invalid type: string \"yes\", expected a boolean"));
+        assert!(Config::parse("schema_version = 99\n")
+            .err()
+            .unwrap()
+            .contains("schema version 99"));
+        assert!(Config::parse("schema_version = -1\n").is_err());
+    }
+
+    #[test]
+    fn test_to_toml_writes_schema_version() {
+        let toml = Config::default().to_toml();
+        assert_eq!(toml, format!("schema_version = {SCHEMA_VERSION}\n"));
+    }
+
+    #[test]
+    fn test_to_env_proc() {
+        let foo = Some("test");
+        assert_eq!(to_env!(foo), "FOO=test");
+    }
+
+    #[test]
+    fn test_set_env_vars_from_struct() {
+        let contents = Config {
+            schema_version: None,
+            quick_setup: Some(true),
+            compose_profiles: Some(vec!["foo".to_string(), "bar".to_string()]),
+            lila_ws_container: Some("image".to_string()),
+            setup_database: Some(true),
+            setup_bbppairings: Some(false),
+            mock_email: Some(false),
+            enable_monitoring: Some(false),
+            enable_rate_limiting: Some(true),
+            su_password: Some("foo".to_string()),
+            password: Some("bar".to_string()),
+            setup_api_tokens: Some(false),
+            lila_domain: Some("baz:8080".to_string()),
+            lila_url: Some("http://baz:8080".to_string()),
+            docker_runtime: Some(Runtime::Podman),
+            repositories: None,
+            services: None,
+        }
+        .to_env();
+
+        assert_eq!(
+            contents,
+            vec![
+                "QUICK_SETUP=true",
+                "COMPOSE_PROFILES=foo,bar",
+                "LILA_WS_CONTAINER=image",
+                "SETUP_DATABASE=true",
+                "SETUP_BBPPAIRINGS=false",
+                "MOCK_EMAIL=false",
+                "ENABLE_MONITORING=false",
+                "ENABLE_RATE_LIMITING=true",
+                "SU_PASSWORD=foo",
+                "PASSWORD=bar",
+                "SETUP_API_TOKENS=false",
+                "LILA_DOMAIN=baz:8080",
+                "LILA_URL=http://baz:8080",
+                "DOCKER_RUNTIME=podman",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_env_removes_empty_lines() {
+        let contents = Config {
+            schema_version: None,
+            quick_setup: None,
+            compose_profiles: None,
+            lila_ws_container: None,
+            setup_database: None,
+            setup_bbppairings: None,
+            mock_email: None,
+            enable_monitoring: None,
+            enable_rate_limiting: None,
+            su_password: None,
+            password: None,
+            setup_api_tokens: None,
+            lila_domain: Some("baz:8080".to_string()),
+            lila_url: Some("http://baz:8080".to_string()),
+            docker_runtime: None,
+            repositories: None,
+            services: None,
+        }
+        .to_env();
+
+        assert_eq!(
+            contents,
+            [
+                "COMPOSE_PROFILES=",
+                "LILA_DOMAIN=baz:8080",
+                "LILA_URL=http://baz:8080"
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_env_quotes_passwords() {
+        let contents = Config {
+            su_password: Some("my pass#word".to_string()),
+            password: Some("it's $HOME".to_string()),
+            ..Default::default()
+        }
+        .to_env();
+
+        assert_eq!(
+            contents,
+            [
+                "COMPOSE_PROFILES=",
+                "SU_PASSWORD='my pass#word'",
+                "PASSWORD=\"it's \\$HOME\"",
+            ]
+            .join("\n")
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn test_env_round_trips_passwords(su_password in "[^\u{0}]*", password in "[^\u{0}]*") {
+            let config = Config {
+                su_password: Some(su_password.clone()),
+                password: Some(password.clone()),
+                lila_url: Some("http://localhost:8080".to_string()),
+                ..Default::default()
+            };
+            let entries = crate::dotenv::parse(&config.to_env()).unwrap();
+            let get = |key: &str| entries.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone());
+
+            prop_assert_eq!(get("SU_PASSWORD"), Some(su_password));
+            prop_assert_eq!(get("PASSWORD"), Some(password));
+            prop_assert_eq!(get("LILA_URL"), Some("http://localhost:8080".to_string()));
+        }
+    }
+
+    #[test]
+    fn test_get_set_unset() {
+        let mut config = valid_config();
+
+        assert_eq!(
+            config.get("lila_domain"),
+            Ok(Some(Value::String("localhost:8080".to_string())))
+        );
+        assert_eq!(config.get("mock_email"), Ok(None));
+
+        config.set("mock_email", "false").unwrap();
+        assert_eq!(config.mock_email, Some(false));
+
+        config
+            .set("compose_profiles", "base, email,search")
+            .unwrap();
+        assert_eq!(
+            config.compose_profiles,
+            Some(vec![
+                "base".to_string(),
+                "email".to_string(),
+                "search".to_string()
+            ])
+        );
+        assert_eq!(
+            display_value(&config.get("compose_profiles").unwrap().unwrap()),
+            "base,email,search"
+        );
+
+        config.set("password", "1234").unwrap();
+        assert_eq!(config.password.as_deref(), Some("1234"));
+        config.set("su_password", "true").unwrap();
+        assert_eq!(config.su_password.as_deref(), Some("true"));
+
+        config.unset("password").unwrap();
+        assert_eq!(config.password, None);
+    }
+
+    #[test]
+    fn test_set_type_errors() {
+        let mut config = valid_config();
+        let err = config.set("mock_email", "maybe").unwrap_err();
+        assert!(err.starts_with("`maybe` is not a valid value for `mock_email`"));
+        assert_eq!(config.mock_email, None);
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let mut config = valid_config();
+        assert!(config
+            .get("mock_mail")
+            .unwrap_err()
+            .contains("not a setting"));
+        assert!(config.set("mock_mail", "true").is_err());
+        assert!(config.unset("mock_mail").is_err());
+        assert!(config.set("schema_version", "2").is_err());
+    }
+
+    #[test]
+    fn test_entries() {
+        let config = Config {
+            schema_version: Some(SCHEMA_VERSION),
+            quick_setup: Some(true),
+            lila_url: Some("http://localhost:8080".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.entries(),
+            [
+                (
+                    "lila_url".to_string(),
+                    Value::String("http://localhost:8080".to_string())
+                ),
+                ("quick_setup".to_string(), Value::Boolean(true)),
+            ]
+        );
+    }
+
+    fn lila_settings(settings: RepositorySettings) -> Config {
+        Config {
+            repositories: Some(BTreeMap::from([("lila".to_string(), settings)])),
+            ..valid_config()
+        }
+    }
+
+    #[test]
+    fn test_repository_settings() {
+        let config = lila_settings(RepositorySettings {
+            fork: Some("https://github.com/me/lila".to_string()),
+            branch: Some("my-feature".to_string()),
+            ..Default::default()
+        });
+        assert!(config.validate().is_ok());
+
+        let lila = config.repository(&Repository::new("lichess-org", "lila"));
+        assert_eq!(lila.fork.as_deref(), Some("https://github.com/me/lila"));
+        assert_eq!(lila.branch.as_deref(), Some("my-feature"));
+        assert_eq!(lila.depth, Some(crate::repository::DEFAULT_DEPTH));
+
+        let lila_ws = Repository::new("lichess-org", "lila-ws");
+        assert_eq!(config.repository(&lila_ws), lila_ws);
+
+        let config = lila_settings(RepositorySettings {
+            full_history: Some(true),
+            ..Default::default()
+        });
+        let lila = config.repository(&Repository::new("lichess-org", "lila"));
+        assert_eq!(lila.depth, None);
+    }
+
+    #[test]
+    fn test_validate_repository_settings() {
+        let config = Config {
+            repositories: Some(BTreeMap::from([
+                (
+                    "lila".to_string(),
+                    RepositorySettings {
+                        depth: Some(0),
+                        ..Default::default()
+                    },
+                ),
+                ("lilla".to_string(), RepositorySettings::default()),
+            ])),
+            ..valid_config()
+        };
+        assert_eq!(invalid_fields(&config), ["repositories", "repositories"]);
+
+        let config = lila_settings(RepositorySettings {
+            fork: Some(String::new()),
+            branch: Some("--upload-pack=x".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(invalid_fields(&config), ["repositories", "repositories"]);
+
+        let config = lila_settings(RepositorySettings {
+            depth: Some(10),
+            full_history: Some(true),
+            ..Default::default()
+        });
+        assert_eq!(invalid_fields(&config), ["repositories"]);
+    }
+
+    #[test]
+    fn test_validate_service_settings() {
+        let service = |settings: ServiceSettings| Config {
+            services: Some(BTreeMap::from([("lila".to_string(), settings)])),
+            ..valid_config()
+        };
+
+        let config = service(ServiceSettings {
+            ports: Some(BTreeMap::from([("9663".to_string(), 19663)])),
+            volumes: Some(vec!["./heapdumps:/heapdumps".to_string()]),
+            environment: Some(BTreeMap::from([(
+                "JAVA_OPTS".to_string(),
+                "-Xmx6g".to_string(),
+            )])),
+            cpus: Some(2.5),
+            memory: Some("8g".to_string()),
+        });
+        assert!(config.validate().is_ok());
+
+        let config = service(ServiceSettings {
+            ports: Some(BTreeMap::from([("http".to_string(), 0)])),
+            volumes: Some(vec!["./heapdumps".to_string()]),
+            environment: Some(BTreeMap::from([("A B".to_string(), String::new())])),
+            cpus: Some(0.0),
+            memory: Some("8 gigs".to_string()),
+        });
+        assert_eq!(invalid_fields(&config), ["services"; 6]);
+    }
+
+    #[test]
+    fn test_is_byte_size() {
+        for size in ["1024", "512m", "4g", "1.5GB", "100k"] {
+            assert!(is_byte_size(size), "{size}");
+        }
+        for size in ["", "g", "0", "4t", "-1g", "4 g"] {
+            assert!(!is_byte_size(size), "{size}");
+        }
+    }
+
+    #[test]
+    fn test_remap_lila_port() {
+        let mut config = Config::default();
+        assert_eq!(config.lila_port(), 8080);
+        config.remap_lila_port(8080, 8085);
+        assert_eq!(config.lila_domain.as_deref(), Some("localhost:8085"));
+        assert_eq!(config.lila_url.as_deref(), Some("http://localhost:8085"));
+        assert!(config.validate().is_ok());
+
+        config.remap_lila_port(9000, 9001);
+        assert_eq!(config.lila_port(), 8085);
+
+        let mut config = Config {
+            lila_domain: Some("192.168.1.2:8080".to_string()),
+            lila_url: Some("https://192.168.1.2:8080".to_string()),
+            ..Default::default()
+        };
+        config.remap_lila_port(8080, 8090);
+        assert_eq!(config.lila_url.as_deref(), Some("https://192.168.1.2:8090"));
+    }
+
+    #[test]
+    fn test_with_docker_runtime_keeps_invalid_settings() {
+        let config = Config {
+            docker_runtime: Some(Runtime::Podman),
+            ..Default::default()
+        };
+        let (toml, env) = config
+            .with_docker_runtime(
+                Some("lila_url = \"localhost:8080\"\ndocker_runtime = \"engine\"\n"),
+                Some("LILA_URL=localhost:8080\nDOCKER_RUNTIME=engine\n"),
+            )
+            .unwrap();
+        assert_eq!(
+            toml,
+            "docker_runtime = \"podman\"\nlila_url = \"localhost:8080\"\n"
+        );
+        assert_eq!(env, "LILA_URL=localhost:8080\nDOCKER_RUNTIME=podman\n");
+
+        let (toml, env) = config.with_docker_runtime(None, None).unwrap();
+        assert_eq!(toml, config.to_toml());
+        assert_eq!(env, config.to_env());
+    }
+
+    #[test]
+    fn test_set_hostname_keeps_remapped_port() {
+        let mut config = Config::default();
+        config.remap_lila_port(8080, 8085);
+        config.set_hostname("192.168.1.2");
+        assert_eq!(config.lila_domain.as_deref(), Some("192.168.1.2:8085"));
+        assert_eq!(config.lila_url.as_deref(), Some("http://192.168.1.2:8085"));
+    }
+
+    #[test]
+    fn test_repository_settings_toml() {
+        let contents = "
+            [repositories.lila]
+            fork = \"https://github.com/me/lila\"
+            depth = 10
+            forkk = \"typo\"
+        ";
+        let parsed = Config::parse(contents).unwrap();
+        assert_eq!(parsed.unknown_keys, ["repositories.lila.forkk"]);
+        assert_eq!(
+            parsed.config.repositories.unwrap()["lila"],
+            RepositorySettings {
+                fork: Some("https://github.com/me/lila".to_string()),
+                depth: Some(10),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_get_set_unset_dotted_keys() {
+        let mut config = valid_config();
+
+        config
+            .set("repositories.lila.fork", "https://github.com/me/lila")
+            .unwrap();
+        config.set("repositories.lila.depth", "50").unwrap();
+        assert_eq!(
+            config.get("repositories.lila.depth"),
+            Ok(Some(Value::Integer(50)))
+        );
+        assert!(config.set("repositories.lila.depth", "deep").is_err());
+        assert!(config.get("repositories.lila.forkk").is_err());
+        assert!(config
+            .entries()
+            .contains(&("repositories.lila.depth".to_string(), Value::Integer(50))));
+
+        config.unset("repositories.lila.fork").unwrap();
+        config.unset("repositories.lila.depth").unwrap();
+        assert_eq!(config.repositories, None);
+    }
+}
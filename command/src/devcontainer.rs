@@ -0,0 +1,118 @@
+use serde::Serialize;
+use serde_json::{json, ser::PrettyFormatter, Map, Value};
+
+use crate::{
+    config::DEFAULT_LILA_PORT,
+    error::{Error, Result},
+    services,
+};
+
+/// The Codespaces configuration, which must stay plain JSON (no comments) to be regenerated
+pub const PATH: &str = ".devcontainer/devcontainer.json";
+
+/// Where Codespaces serves the forwarded lila port
+fn lila_env() -> [(&'static str, String); 2] {
+    let domain = format!("${{localEnv:CODESPACE_NAME}}-{DEFAULT_LILA_PORT}.app.github.dev");
+    let url = format!("https://{domain}");
+    [("LILA_DOMAIN", domain), ("LILA_URL", url)]
+}
+
+/// A label for lila and every port in the service catalog. Only lila opens on its own.
+fn ports_attributes() -> Value {
+    let mut ports = Map::new();
+    ports.insert(
+        DEFAULT_LILA_PORT.to_string(),
+        json!({ "label": "Lichess", "onAutoForward": "openPreview" }),
+    );
+    for port in services::catalog()
+        .iter()
+        .flat_map(|service| &service.ports)
+    {
+        let label = match port.credentials {
+            Some(credentials) => format!("{} ({credentials})", port.label),
+            None => port.label.to_string(),
+        };
+        ports.insert(
+            port.number.to_string(),
+            json!({ "label": label, "onAutoForward": "silent" }),
+        );
+    }
+    Value::Object(ports)
+}
+
+/// `devcontainer` with the lila environment and port labels replaced, keeping everything else
+pub fn render(devcontainer: &str) -> Result<String> {
+    let mut devcontainer: Map<String, Value> = serde_json::from_str(devcontainer)?;
+    let remote_env = devcontainer.entry("remoteEnv").or_insert_with(|| json!({}));
+    if !remote_env.is_object() {
+        *remote_env = json!({});
+    }
+    if let Value::Object(remote_env) = remote_env {
+        for (key, value) in lila_env() {
+            remote_env.insert(key.to_string(), Value::String(value));
+        }
+    }
+    devcontainer.insert("portsAttributes".to_string(), ports_attributes());
+
+    let mut rendered = vec![];
+    let mut serializer = serde_json::Serializer::with_formatter(
+        &mut rendered,
+        PrettyFormatter::with_indent(b"    "),
+    );
+    devcontainer
+        .serialize(&mut serializer)
+        .map_err(Error::json_output)?;
+    rendered.push(b'\n');
+    Ok(String::from_utf8_lossy(&rendered).to_string())
+}
+
+/// Regenerate the file at `PATH`, returning whether it changed
+pub fn write() -> Result<bool> {
+    let current = std::fs::read_to_string(PATH)?;
+    let rendered = render(&current)?;
+    if rendered == current {
+        return Ok(false);
+    }
+    std::fs::write(PATH, rendered)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_keeps_other_settings() {
+        let rendered = render(
+            r#"{"remoteEnv": {"OTHER": "1"}, "portsAttributes": {"1234": {}}, "postAttachCommand": "./lila-docker start"}"#,
+        )
+        .unwrap();
+        let rendered: Value = serde_json::from_str(&rendered).unwrap();
+
+        assert_eq!(rendered["postAttachCommand"], "./lila-docker start");
+        assert_eq!(
+            rendered["remoteEnv"]["LILA_URL"],
+            "https://${localEnv:CODESPACE_NAME}-8080.app.github.dev"
+        );
+        assert_eq!(rendered["remoteEnv"]["OTHER"], "1");
+        assert!(rendered["portsAttributes"].get("1234").is_none());
+        assert_eq!(
+            rendered["portsAttributes"]["8086"]["label"],
+            "InfluxDB Metrics (u: admin / p: password)"
+        );
+        assert_eq!(
+            rendered["portsAttributes"]["8080"]["onAutoForward"],
+            "openPreview"
+        );
+    }
+
+    #[test]
+    fn test_devcontainer_is_up_to_date() {
+        let path = format!("{}/../{PATH}", env!("CARGO_MANIFEST_DIR"));
+        let current = std::fs::read_to_string(path).unwrap();
+        assert!(
+            render(&current).unwrap() == current,
+            "{PATH} does not match the service catalog, run `./lila-docker devcontainer`"
+        );
+    }
+}
@@ -0,0 +1,299 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::process::Command;
+
+use crate::{dotenv, error::Result};
+
+/// The oldest Docker Compose that reads the `include:` in `compose.yml`
+pub const MIN_COMPOSE_VERSION: Version = Version(2, 20, 0);
+
+/// The oldest Docker Compose that supports `!override`, used to remap ports
+pub const OVERRIDE_COMPOSE_VERSION: Version = Version(2, 24, 4);
+
+/// A `major.minor.patch` version
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Version(pub u32, pub u32, pub u32);
+
+impl Version {
+    /// Read `v2.29.1`, `2.29.1-desktop.1`, `2.29` and the like
+    pub fn parse(version: &str) -> Option<Self> {
+        let version = version.trim().trim_start_matches('v');
+        let mut parts = version.split(['.', '-', '+']).map(str::parse::<u32>);
+        let major = parts.next()?.ok()?;
+        let minor = parts.next()?.ok()?;
+        let patch = parts.next().and_then(Result::ok).unwrap_or(0);
+        Some(Self(major, minor, patch))
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+/// What runs the containers. Everything but Docker Engine maps the container's root user
+/// to the host user, so the containers run as root there.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Runtime {
+    Desktop,
+    Engine,
+    /// Docker Engine running as an unprivileged user
+    Rootless,
+    Podman,
+    Colima,
+}
+
+impl Runtime {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Desktop => "Docker Desktop",
+            Self::Engine => "Docker Engine",
+            Self::Rootless => "rootless Docker Engine",
+            Self::Podman => "Podman",
+            Self::Colima => "Colima",
+        }
+    }
+
+    /// The `USER_ID` and `GROUP_ID` the containers should run as to write to `./repos`
+    pub fn container_user(self) -> Option<(u32, u32)> {
+        match self {
+            Self::Engine => host_user(),
+            // The container's root is the host user, or the VM shares files as them
+            Self::Desktop | Self::Rootless | Self::Podman | Self::Colima => Some((0, 0)),
+        }
+    }
+}
+
+impl std::fmt::Display for Runtime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Desktop => "desktop",
+            Self::Engine => "engine",
+            Self::Rootless => "rootless",
+            Self::Podman => "podman",
+            Self::Colima => "colima",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// The running container runtime
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Info {
+    pub runtime: Runtime,
+    pub server_version: String,
+}
+
+/// The running container runtime, `None` if Docker is not installed or not running
+pub fn info() -> Option<Info> {
+    let output = Command::new("docker")
+        .args(["info", "--format", "{{json .}}"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let info = serde_json::from_slice(&output.stdout).ok()?;
+    let cli_version = Command::new("docker")
+        .arg("--version")
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).to_string())
+        .unwrap_or_default();
+    let docker_host = std::env::var("DOCKER_HOST").unwrap_or_default();
+    Some(parse_info(&info, &cli_version, &docker_host))
+}
+
+/// Tell the runtimes apart by the output of `docker info --format '{{json .}}'`,
+/// `docker --version` and `$DOCKER_HOST`
+fn parse_info(info: &Value, cli_version: &str, docker_host: &str) -> Info {
+    let text = |key: &str| info[key].as_str().unwrap_or_default();
+    let runtime = if cli_version.to_lowercase().contains("podman")
+        || docker_host.contains("podman")
+        || info.get("host").is_some()
+    {
+        // `podman info`, when `docker` is Podman's drop-in, has its own lowercase keys
+        Runtime::Podman
+    } else if text("OperatingSystem").starts_with("Docker Desktop") {
+        Runtime::Desktop
+    } else if text("Name").starts_with("colima") || docker_host.contains("colima") {
+        Runtime::Colima
+    } else if info["SecurityOptions"].as_array().is_some_and(|options| {
+        options.iter().any(|option| {
+            option
+                .as_str()
+                .is_some_and(|option| option.contains("name=rootless"))
+        })
+    }) {
+        Runtime::Rootless
+    } else {
+        Runtime::Engine
+    };
+    let server_version = info["ServerVersion"]
+        .as_str()
+        .or_else(|| info["version"]["Version"].as_str())
+        .unwrap_or("(unknown version)")
+        .to_string();
+    Info {
+        runtime,
+        server_version,
+    }
+}
+
+/// The version of the Docker Compose plugin, `None` if it is not installed
+pub fn compose_version() -> Option<String> {
+    let output = Command::new("docker")
+        .args(["compose", "version", "--short"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Where Docker Compose reads `USER_ID` and `GROUP_ID` from
+pub const ENV_FILE: &str = ".env";
+
+/// The `USER_ID` and `GROUP_ID` written to `.env`, if they have been
+pub fn env_user() -> Option<(u32, u32)> {
+    let contents = std::fs::read_to_string(ENV_FILE).ok()?;
+    let id = |key: &str| dotenv::get(&contents, key)?.parse().ok();
+    Some((id("USER_ID")?, id("GROUP_ID")?))
+}
+
+/// Write `USER_ID` and `GROUP_ID` to `.env`, keeping any other entries in it
+pub fn write_env_user((user_id, group_id): (u32, u32)) -> Result {
+    let contents = std::fs::read_to_string(ENV_FILE).unwrap_or_default();
+    std::fs::write(
+        ENV_FILE,
+        dotenv::update(
+            &contents,
+            &[
+                ("USER_ID", &user_id.to_string()),
+                ("GROUP_ID", &group_id.to_string()),
+            ],
+        ),
+    )?;
+    Ok(())
+}
+
+/// Who owns the files the containers write, on the host. That is the `USER_ID` and `GROUP_ID`
+/// in `.env` on Docker Engine, and the host user on the runtimes that run the containers as root.
+pub fn files_owner(runtime: Option<Runtime>, env_user: Option<(u32, u32)>) -> Option<(u32, u32)> {
+    match runtime {
+        None | Some(Runtime::Engine) => env_user,
+        Some(_) => host_user(),
+    }
+}
+
+/// The user and group running lila-docker
+#[cfg(unix)]
+#[allow(clippy::unnecessary_wraps)] // there is no such user on other platforms
+pub fn host_user() -> Option<(u32, u32)> {
+    // SAFETY: getuid and getgid cannot fail and have no preconditions
+    Some(unsafe { (libc::getuid(), libc::getgid()) })
+}
+
+#[cfg(not(unix))]
+pub fn host_user() -> Option<(u32, u32)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(Version::parse("2.29.1"), Some(Version(2, 29, 1)));
+        assert_eq!(Version::parse("v2.24.6-desktop.1"), Some(Version(2, 24, 6)));
+        assert_eq!(Version::parse("2.20\n"), Some(Version(2, 20, 0)));
+        assert_eq!(Version::parse("unknown"), None);
+        assert!(Version(2, 24, 3) < OVERRIDE_COMPOSE_VERSION);
+        assert!(Version(2, 100, 0) > OVERRIDE_COMPOSE_VERSION);
+    }
+
+    #[test]
+    fn test_parse_info() {
+        let info = |json: &str| serde_json::from_str::<Value>(json).unwrap();
+        let runtime = |json: &str, cli_version: &str, docker_host: &str| {
+            parse_info(&info(json), cli_version, docker_host).runtime
+        };
+        let engine = r#"{"OperatingSystem": "Ubuntu 24.04.1 LTS", "ServerVersion": "27.3.1", "Name": "laptop", "SecurityOptions": ["name=apparmor", "name=seccomp,profile=builtin"]}"#;
+
+        assert_eq!(
+            parse_info(&info(engine), "Docker version 27.3.1", ""),
+            Info {
+                runtime: Runtime::Engine,
+                server_version: "27.3.1".to_string(),
+            }
+        );
+        assert_eq!(
+            runtime(
+                r#"{"OperatingSystem": "Docker Desktop", "Name": "docker-desktop"}"#,
+                "",
+                ""
+            ),
+            Runtime::Desktop
+        );
+        assert_eq!(
+            runtime(
+                r#"{"OperatingSystem": "Ubuntu 24.04 LTS", "Name": "colima"}"#,
+                "",
+                ""
+            ),
+            Runtime::Colima
+        );
+        assert_eq!(
+            runtime(
+                r#"{"SecurityOptions": ["name=seccomp,profile=builtin", "name=rootless", "name=cgroupns"]}"#,
+                "",
+                "unix:///run/user/1000/docker.sock"
+            ),
+            Runtime::Rootless
+        );
+        assert_eq!(
+            runtime(engine, "", "unix:///run/user/1000/podman/podman.sock"),
+            Runtime::Podman
+        );
+        assert_eq!(
+            parse_info(
+                &info(r#"{"host": {"os": "linux"}, "version": {"Version": "5.2.3"}}"#),
+                "podman version 5.2.3",
+                ""
+            ),
+            Info {
+                runtime: Runtime::Podman,
+                server_version: "5.2.3".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_files_owner() {
+        assert_eq!(files_owner(None, Some((1000, 1000))), Some((1000, 1000)));
+        assert_eq!(
+            files_owner(Some(Runtime::Engine), Some((1000, 1000))),
+            Some((1000, 1000))
+        );
+        assert_eq!(
+            files_owner(Some(Runtime::Podman), Some((0, 0))),
+            host_user()
+        );
+        assert_eq!(files_owner(Some(Runtime::Engine), None), None);
+    }
+
+    #[test]
+    fn test_runtime_serializes_as_display() {
+        for runtime in [
+            Runtime::Desktop,
+            Runtime::Engine,
+            Runtime::Rootless,
+            Runtime::Podman,
+            Runtime::Colima,
+        ] {
+            assert_eq!(
+                serde_json::to_string(&runtime).unwrap(),
+                format!("\"{runtime}\"")
+            );
+        }
+    }
+}
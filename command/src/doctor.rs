@@ -0,0 +1,565 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    compose::{self, PublishedPort},
+    docker::{self, Version},
+    error::Result,
+    ports, repository,
+    resources::{HostLimits, Resources},
+    services, Config,
+};
+
+/// Below this, `./lila-docker ui --watch` can run out of inotify watches on Linux
+const MIN_INOTIFY_WATCHES: u64 = 65536;
+
+/// What common editor and bundler docs suggest, with room to spare
+const RECOMMENDED_INOTIFY_WATCHES: u64 = 524_288;
+
+#[derive(Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// What a check found, and how to fix it when it is not a pass
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Finding {
+    pub outcome: Outcome,
+    pub message: String,
+    pub fix: Option<String>,
+}
+
+impl Finding {
+    fn pass(message: impl Into<String>) -> Self {
+        Self {
+            outcome: Outcome::Pass,
+            message: message.into(),
+            fix: None,
+        }
+    }
+
+    fn warn(message: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            outcome: Outcome::Warn,
+            message: message.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn fail(message: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            outcome: Outcome::Fail,
+            message: message.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+/// The result of one check, as printed by `doctor --json`
+#[derive(Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct Report {
+    pub name: &'static str,
+    pub outcome: Outcome,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fix: Option<String>,
+}
+
+/// Everything the checks look at, gathered up front so the checks themselves
+/// are plain functions of it.
+#[derive(Clone, Debug, Default)]
+pub struct Facts {
+    pub docker: Option<docker::Info>,
+    /// The runtime recorded in `settings.toml` by the last `./lila-docker start`
+    pub recorded_runtime: Option<docker::Runtime>,
+    pub compose_version: Option<String>,
+    pub git: bool,
+    pub git_lfs: bool,
+    pub limits: HostLimits,
+    /// What the enabled services are estimated to need
+    pub required: Resources,
+    /// `fs.inotify.max_user_watches`, on Linux
+    pub inotify_watches: Option<u64>,
+    /// Whether the setup has been run
+    pub set_up: bool,
+    /// Why `settings.toml` is invalid, if it is
+    pub settings_errors: Vec<String>,
+    /// The `USER_ID` and `GROUP_ID` in `.env`
+    pub env_user: Option<(u32, u32)>,
+    /// The `USER_ID` and `GROUP_ID` the containers should run as
+    pub expected_user: Option<(u32, u32)>,
+    /// Who the files the containers write are owned by, see `docker::files_owner`
+    pub files_owner: Option<(u32, u32)>,
+    /// Checkouts in `./repos` that are not owned by `files_owner`, with their owner
+    pub misowned: Vec<(PathBuf, (u32, u32))>,
+    pub port_conflicts: Vec<PublishedPort>,
+}
+
+impl Facts {
+    /// Look at the host. `config` is the result of loading `settings.toml`,
+    /// which may have failed.
+    pub fn gather(config: Result<Config>) -> Self {
+        let (config, settings_errors) = match config {
+            Ok(config) => {
+                let errors = config
+                    .validate()
+                    .err()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect();
+                (config, errors)
+            }
+            Err(err) => (Config::default(), vec![err.to_string()]),
+        };
+
+        let docker = docker::info();
+        let env_user = docker::env_user();
+        let files_owner = docker::files_owner(
+            config
+                .docker_runtime
+                .or(docker.as_ref().map(|info| info.runtime)),
+            env_user,
+        );
+        let misowned = files_owner
+            .map(|(user_id, group_id)| {
+                repository::checkouts(Path::new("."))
+                    .unwrap_or_default()
+                    .into_iter()
+                    .skip(1)
+                    .filter_map(|(_, path)| {
+                        repository::owner_mismatch(&path, user_id, group_id)
+                            .map(|owner| (path, owner))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let set_up = config.compose_profiles.is_some();
+        let profiles = config.compose_profiles.clone().unwrap_or_default();
+        let port_conflicts = if docker.is_some() && !profiles.is_empty() {
+            let running = ports::running_services(&config);
+            compose::published_ports(&config)
+                .map(|published| {
+                    ports::conflicts(&published, &running, ports::is_free)
+                        .into_iter()
+                        .cloned()
+                        .collect()
+                })
+                .unwrap_or_default()
+        } else {
+            vec![]
+        };
+
+        Self {
+            expected_user: docker
+                .as_ref()
+                .and_then(|info| info.runtime.container_user()),
+            docker,
+            recorded_runtime: config.docker_runtime,
+            compose_version: docker::compose_version(),
+            git: repository::git(Path::new("."), &["--version"]).is_ok(),
+            git_lfs: repository::has_git_lfs(),
+            limits: HostLimits::detect(),
+            required: Resources::BASE
+                + services::enabled_services(&profiles)
+                    .iter()
+                    .map(|service| service.resources)
+                    .sum::<Resources>(),
+            inotify_watches: std::fs::read_to_string("/proc/sys/fs/inotify/max_user_watches")
+                .ok()
+                .and_then(|watches| watches.trim().parse().ok()),
+            set_up,
+            settings_errors,
+            env_user,
+            files_owner,
+            misowned,
+            port_conflicts,
+        }
+    }
+}
+
+/// One thing to check about the host
+pub struct Check {
+    pub name: &'static str,
+    run: fn(&Facts) -> Finding,
+}
+
+/// Every check that `doctor` runs, in order.
+/// Adding a check only requires adding it here.
+pub fn checks() -> Vec<Check> {
+    vec![
+        Check {
+            name: "docker",
+            run: check_docker,
+        },
+        Check {
+            name: "runtime",
+            run: check_runtime,
+        },
+        Check {
+            name: "compose",
+            run: check_compose,
+        },
+        Check {
+            name: "git",
+            run: check_git,
+        },
+        Check {
+            name: "git-lfs",
+            run: check_git_lfs,
+        },
+        Check {
+            name: "resources",
+            run: check_resources,
+        },
+        Check {
+            name: "inotify",
+            run: check_inotify,
+        },
+        Check {
+            name: "settings",
+            run: check_settings,
+        },
+        Check {
+            name: "user-ids",
+            run: check_user_ids,
+        },
+        Check {
+            name: "ownership",
+            run: check_ownership,
+        },
+        Check {
+            name: "ports",
+            run: check_ports,
+        },
+    ]
+}
+
+pub fn run(facts: &Facts) -> Vec<Report> {
+    checks()
+        .into_iter()
+        .map(|check| {
+            let finding = (check.run)(facts);
+            Report {
+                name: check.name,
+                outcome: finding.outcome,
+                message: finding.message,
+                fix: finding.fix,
+            }
+        })
+        .collect()
+}
+
+fn check_docker(facts: &Facts) -> Finding {
+    match &facts.docker {
+        Some(info) => Finding::pass(format!(
+            "{} {} is running",
+            info.runtime.label(),
+            info.server_version
+        )),
+        None => Finding::fail(
+            "Docker is not installed or not running",
+            "install Docker from https://docs.docker.com/get-docker/, or start Docker Desktop",
+        ),
+    }
+}
+
+fn check_runtime(facts: &Facts) -> Finding {
+    match (facts.recorded_runtime, &facts.docker) {
+        (Some(recorded), Some(info)) if recorded != info.runtime => Finding::warn(
+            format!(
+                "settings.toml says the containers run on {}, but {} is running now",
+                recorded.label(),
+                info.runtime.label()
+            ),
+            "run `./lila-docker start` to detect it again",
+        ),
+        (Some(recorded), _) => Finding::pass(format!("Set up for {}", recorded.label())),
+        (None, _) => Finding::pass("Not detected yet"),
+    }
+}
+
+fn check_compose(facts: &Facts) -> Finding {
+    let Some(version) = &facts.compose_version else {
+        return Finding::fail(
+            "The Docker Compose plugin is not installed",
+            "install it from https://docs.docker.com/compose/install/",
+        );
+    };
+    match Version::parse(version) {
+        Some(parsed) if parsed < docker::MIN_COMPOSE_VERSION => Finding::fail(
+            format!("Docker Compose {version} cannot read compose.yml"),
+            format!(
+                "upgrade to Docker Compose {} or newer",
+                docker::MIN_COMPOSE_VERSION
+            ),
+        ),
+        Some(parsed) if parsed < docker::OVERRIDE_COMPOSE_VERSION => Finding::warn(
+            format!("Docker Compose {version} cannot remap ports in settings.toml"),
+            format!(
+                "upgrade to Docker Compose {} or newer",
+                docker::OVERRIDE_COMPOSE_VERSION
+            ),
+        ),
+        Some(_) => Finding::pass(format!("Docker Compose {version}")),
+        None => Finding::warn(
+            format!("Could not read the Docker Compose version `{version}`"),
+            format!(
+                "make sure `docker compose version` prints {} or newer",
+                docker::MIN_COMPOSE_VERSION
+            ),
+        ),
+    }
+}
+
+fn check_git(facts: &Facts) -> Finding {
+    if facts.git {
+        Finding::pass("git is installed")
+    } else {
+        Finding::fail(
+            "git is not installed",
+            "install it from https://git-scm.com/downloads",
+        )
+    }
+}
+
+fn check_git_lfs(facts: &Facts) -> Finding {
+    if facts.git_lfs {
+        Finding::pass("Git LFS is installed")
+    } else {
+        Finding::warn(
+            "Git LFS is not installed, so lila's images and sounds are not downloaded",
+            "install it from https://git-lfs.com/, then run `./lila-docker lfs --fetch`",
+        )
+    }
+}
+
+fn check_resources(facts: &Facts) -> Finding {
+    let shortfalls = facts.limits.shortfalls(facts.required);
+    if shortfalls.is_empty() {
+        Finding::pass(format!(
+            "Enough for the enabled services ({})",
+            facts.required
+        ))
+    } else {
+        Finding::warn(
+            shortfalls.join(", "),
+            "free up resources, raise Docker Desktop's limits, or disable some services",
+        )
+    }
+}
+
+fn check_inotify(facts: &Facts) -> Finding {
+    match facts.inotify_watches {
+        None => Finding::pass("No inotify limit to check on this system"),
+        Some(watches) if watches < MIN_INOTIFY_WATCHES => Finding::warn(
+            format!("Only {watches} inotify watches are allowed, which `./lila-docker ui --watch` can run out of"),
+            format!("run `echo fs.inotify.max_user_watches={RECOMMENDED_INOTIFY_WATCHES} | sudo tee /etc/sysctl.d/99-lila-docker.conf && sudo sysctl --system`"),
+        ),
+        Some(watches) => Finding::pass(format!("{watches} inotify watches are allowed")),
+    }
+}
+
+fn check_settings(facts: &Facts) -> Finding {
+    if !facts.set_up {
+        Finding::pass("Not set up yet")
+    } else if facts.settings_errors.is_empty() {
+        Finding::pass("settings.toml is valid")
+    } else {
+        Finding::fail(
+            facts.settings_errors.join("\n"),
+            "run `./lila-docker config check` for suggested fixes",
+        )
+    }
+}
+
+fn check_user_ids(facts: &Facts) -> Finding {
+    match (facts.env_user, facts.expected_user) {
+        (None, _) if !facts.set_up => Finding::pass("Not set up yet"),
+        (None, _) => Finding::warn(
+            ".env has no USER_ID and GROUP_ID for the containers to run as",
+            "run `./lila-docker start` to write them",
+        ),
+        (Some((user_id, group_id)), Some(expected)) if (user_id, group_id) != expected => {
+            Finding::warn(
+                format!(
+                    "The containers run as {user_id}:{group_id}, but {}:{} is expected with this Docker",
+                    expected.0, expected.1
+                ),
+                "delete .env and run `./lila-docker start` to write it again",
+            )
+        }
+        (Some((user_id, group_id)), _) => {
+            Finding::pass(format!("The containers run as {user_id}:{group_id}"))
+        }
+    }
+}
+
+fn check_ownership(facts: &Facts) -> Finding {
+    let Some((user_id, group_id)) = facts.files_owner else {
+        return Finding::pass("No container user to compare with yet");
+    };
+    if facts.misowned.is_empty() {
+        return Finding::pass(format!("./repos is owned by {user_id}:{group_id}"));
+    }
+    Finding::warn(
+        facts
+            .misowned
+            .iter()
+            .map(|(path, (uid, gid))| format!("{} is owned by {uid}:{gid}", path.display()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        format!(
+            "run `sudo chown -R {user_id}:{group_id} {}`",
+            facts
+                .misowned
+                .iter()
+                .map(|(path, _)| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        ),
+    )
+}
+
+fn check_ports(facts: &Facts) -> Finding {
+    if facts.port_conflicts.is_empty() {
+        return Finding::pass("The host ports of the enabled services are free");
+    }
+    Finding::warn(
+        facts
+            .port_conflicts
+            .iter()
+            .map(|port| format!("{} ({}) is already in use", port.host, port.service))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        "run `./lila-docker ports` to publish them on other ports",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn healthy() -> Facts {
+        Facts {
+            docker: Some(docker::Info {
+                runtime: docker::Runtime::Engine,
+                server_version: "27.3.1".to_string(),
+            }),
+            recorded_runtime: Some(docker::Runtime::Engine),
+            compose_version: Some("2.29.7".to_string()),
+            git: true,
+            git_lfs: true,
+            inotify_watches: Some(524_288),
+            set_up: true,
+            env_user: Some((1000, 1000)),
+            files_owner: Some((1000, 1000)),
+            expected_user: Some((1000, 1000)),
+            ..Default::default()
+        }
+    }
+
+    fn outcomes(facts: &Facts) -> Vec<(&'static str, Outcome)> {
+        run(facts)
+            .into_iter()
+            .filter(|report| report.outcome != Outcome::Pass)
+            .map(|report| (report.name, report.outcome))
+            .collect()
+    }
+
+    #[test]
+    fn test_healthy_host_passes() {
+        assert!(outcomes(&healthy()).is_empty());
+        assert!(outcomes(&Facts {
+            set_up: false,
+            env_user: None,
+            files_owner: None,
+            ..healthy()
+        })
+        .is_empty());
+    }
+
+    #[test]
+    fn test_findings() {
+        let facts = Facts {
+            docker: Some(docker::Info {
+                runtime: docker::Runtime::Desktop,
+                server_version: "4.34.2".to_string(),
+            }),
+            compose_version: Some("2.21.0".to_string()),
+            git_lfs: false,
+            inotify_watches: Some(8192),
+            settings_errors: vec!["`lila_url`: has no scheme".to_string()],
+            expected_user: Some((0, 0)),
+            misowned: vec![(PathBuf::from("repos/lila"), (0, 0))],
+            port_conflicts: vec![PublishedPort {
+                service: "caddy".to_string(),
+                container: "80".to_string(),
+                host: 8080,
+                default_host: 8080,
+            }],
+            ..healthy()
+        };
+        assert_eq!(
+            outcomes(&facts),
+            [
+                ("runtime", Outcome::Warn),
+                ("compose", Outcome::Warn),
+                ("git-lfs", Outcome::Warn),
+                ("inotify", Outcome::Warn),
+                ("settings", Outcome::Fail),
+                ("user-ids", Outcome::Warn),
+                ("ownership", Outcome::Warn),
+                ("ports", Outcome::Warn),
+            ]
+        );
+
+        let facts = Facts {
+            compose_version: Some("2.17.2".to_string()),
+            ..healthy()
+        };
+        assert_eq!(outcomes(&facts), [("compose", Outcome::Fail)]);
+
+        let facts = Facts {
+            docker: None,
+            ..healthy()
+        };
+        assert_eq!(outcomes(&facts), [("docker", Outcome::Fail)]);
+    }
+
+    #[test]
+    fn test_every_finding_has_a_fix() {
+        let facts = Facts {
+            compose_version: None,
+            git: false,
+            env_user: None,
+            files_owner: None,
+            ..Default::default()
+        };
+        for report in run(&facts) {
+            assert_eq!(
+                report.fix.is_some(),
+                report.outcome != Outcome::Pass,
+                "{report:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_report_json() {
+        let report = Report {
+            name: "git",
+            outcome: Outcome::Fail,
+            message: "git is not installed".to_string(),
+            fix: Some("install it".to_string()),
+        };
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            r#"{"name":"git","outcome":"fail","message":"git is not installed","fix":"install it"}"#
+        );
+    }
+}
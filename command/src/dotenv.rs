@@ -0,0 +1,226 @@
+//! The `settings.env` format, shared by the `lila-docker` wrapper (which sources
+//! it with `set -a`) and Docker Compose `--env-file`.
+//!
+//! Every value is written in one of three forms, each of which means the same
+//! thing to bash and to Compose:
+//!
+//! - `KEY=value` when the value only contains [`PLAIN_CHARS`]
+//! - `KEY='value'` when it contains no single quote (no escapes, no expansion)
+//! - `KEY="value"` otherwise, with `\`, `"`, `$` and `` ` `` escaped by a backslash
+
+/// Punctuation that can appear in an unquoted value
+const PLAIN_CHARS: &str = "_-.,:/@+%=";
+
+/// Characters that are backslash-escaped inside a double-quoted value
+const ESCAPED_CHARS: [char; 4] = ['\\', '"', '$', '`'];
+
+fn is_plain(value: &str) -> bool {
+    value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || PLAIN_CHARS.contains(c))
+}
+
+/// Quote `value` so it reads back unchanged.
+pub fn quote(value: &str) -> String {
+    if is_plain(value) {
+        value.to_string()
+    } else if !value.contains('\'') {
+        format!("'{value}'")
+    } else {
+        let mut quoted = String::with_capacity(value.len() + 2);
+        quoted.push('"');
+        for c in value.chars() {
+            if ESCAPED_CHARS.contains(&c) {
+                quoted.push('\\');
+            }
+            quoted.push(c);
+        }
+        quoted.push('"');
+        quoted
+    }
+}
+
+pub fn line(key: &str, value: &str) -> String {
+    format!("{key}={}", quote(value))
+}
+
+/// Parse the contents of an env file written with [`line`].
+/// Quoted values may span several lines.
+pub fn parse(contents: &str) -> Result<Vec<(String, String)>, String> {
+    let mut entries = vec![];
+    let mut chars = contents.chars().peekable();
+    let mut line_number = 1;
+
+    while chars.peek().is_some() {
+        let mut key = String::new();
+        for c in chars.by_ref() {
+            match c {
+                '=' => break,
+                '\n' => return Err(format!("line {line_number}: expected `KEY=value`")),
+                c => key.push(c),
+            }
+        }
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("line {line_number}: invalid key `{key}`"));
+        }
+
+        let mut value = String::new();
+        match chars.peek() {
+            Some('\'') => {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => {
+                            line_number += usize::from(c == '\n');
+                            value.push(c);
+                        }
+                        None => return Err(format!("{key}: unterminated single quote")),
+                    }
+                }
+            }
+            Some('"') => {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) if ESCAPED_CHARS.contains(&c) => value.push(c),
+                            Some(c) => return Err(format!("{key}: unsupported escape `\\{c}`")),
+                            None => return Err(format!("{key}: unterminated double quote")),
+                        },
+                        Some(c) => {
+                            line_number += usize::from(c == '\n');
+                            value.push(c);
+                        }
+                        None => return Err(format!("{key}: unterminated double quote")),
+                    }
+                }
+            }
+            _ => {
+                while let Some(&c) = chars.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    value.push(c);
+                    chars.next();
+                }
+                if !is_plain(&value) {
+                    return Err(format!("{key}: unquoted value `{value}` must be quoted"));
+                }
+            }
+        }
+
+        match chars.next() {
+            None | Some('\n') => line_number += 1,
+            Some(c) => return Err(format!("{key}: unexpected `{c}` after the value")),
+        }
+
+        entries.push((key, value));
+    }
+
+    Ok(entries)
+}
+
+/// The value of `key` in an env file that may also hold lines that lila-docker did not
+/// write, such as comments. `None` if it is missing or cannot be read.
+pub fn get(contents: &str, key: &str) -> Option<String> {
+    contents.lines().rev().find_map(|entry| {
+        entry.strip_prefix(key)?.strip_prefix('=')?;
+        parse(entry).ok()?.pop().map(|(_, value)| value)
+    })
+}
+
+/// Set `entries` in the env file `contents`, replacing the lines that already set them
+/// and appending the others. Every other line is kept as it is.
+pub fn update(contents: &str, entries: &[(&str, &str)]) -> String {
+    let mut lines: Vec<String> = vec![];
+    let mut written: Vec<&str> = vec![];
+    for existing in contents.lines() {
+        let entry = entries.iter().find(|(key, _)| {
+            existing
+                .strip_prefix(key)
+                .is_some_and(|rest| rest.starts_with('='))
+        });
+        match entry {
+            Some((key, _)) if written.contains(key) => {}
+            Some((key, value)) => {
+                lines.push(line(key, value));
+                written.push(key);
+            }
+            None => lines.push(existing.to_string()),
+        }
+    }
+    for (key, value) in entries {
+        if !written.contains(key) {
+            lines.push(line(key, value));
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("http://baz:8080"), "http://baz:8080");
+        assert_eq!(quote("foo,bar"), "foo,bar");
+        assert_eq!(quote(""), "");
+        assert_eq!(quote("pa ss#$word"), "'pa ss#$word'");
+        assert_eq!(quote(r#"it's "$x" \ `y`"#), r#""it's \"\$x\" \\ \`y\`""#);
+    }
+
+    #[test]
+    fn test_parse() {
+        let entries = parse("A=1\nB='x y'\nC=\"it's\"\nD=\n").unwrap();
+        assert_eq!(
+            entries,
+            [
+                ("A".to_string(), "1".to_string()),
+                ("B".to_string(), "x y".to_string()),
+                ("C".to_string(), "it's".to_string()),
+                ("D".to_string(), String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("A=x y").is_err());
+        assert!(parse("A='x").is_err());
+        assert!(parse("A=\"\\n\"").is_err());
+        assert!(parse("A='x'y").is_err());
+        assert!(parse("=x").is_err());
+        assert!(parse("A B=x").is_err());
+    }
+
+    #[test]
+    fn test_get() {
+        let contents =
+            "# my settings\nUSER_ID=1000\nCOMPOSE_PROJECT_NAME=lichess\nUSER_ID_EXTRA=5\n";
+        assert_eq!(get(contents, "USER_ID").as_deref(), Some("1000"));
+        assert_eq!(get(contents, "GROUP_ID"), None);
+        assert_eq!(get("A=1\nA=2", "A").as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn test_update_keeps_other_entries() {
+        let contents = "# my settings\nUSER_ID=0\nCOMPOSE_PROJECT_NAME=lichess\nUSER_ID=0\n";
+        assert_eq!(
+            update(contents, &[("USER_ID", "1000"), ("GROUP_ID", "1000")]),
+            "# my settings\nUSER_ID=1000\nCOMPOSE_PROJECT_NAME=lichess\nGROUP_ID=1000\n"
+        );
+        assert_eq!(update("", &[("USER_ID", "0")]), "USER_ID=0\n");
+    }
+
+    proptest! {
+        #[test]
+        fn test_round_trip(value in "[^\u{0}]*") {
+            let entries = parse(&line("KEY", &value)).unwrap();
+            prop_assert_eq!(entries, vec![("KEY".to_string(), value)]);
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::{fmt, io::ErrorKind, process::ExitCode};
+
+/// Everything that can make a command fail, each with its own exit code
+/// so `./lila-docker` can tell them apart.
+///
+/// | Code | Variant     | Meaning                                                   |
+/// |------|-------------|-----------------------------------------------------------|
+/// | 1    | `Io`        | Reading or writing a file failed                          |
+/// | 2    |             | Invalid command-line arguments (reported by clap)         |
+/// | 3    | `Config`    | `settings.toml`, the answers file or a setting is invalid |
+/// | 4    | `Git`       | A git command failed                                      |
+/// | 5    | `Docker`    | Docker is missing or a Docker command failed              |
+/// | 6    | `Prompt`    | A prompt could not be shown, e.g. there is no terminal    |
+/// | 7    | `Cancelled` | The user cancelled or declined to continue                |
+/// | 8    | `Host`      | `doctor` found something the host is missing              |
+/// | 9    | `Unset`     | `config get` was asked for a setting that is not set      |
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Config(String),
+    Git(String),
+    Docker(String),
+    Prompt(std::io::Error),
+    Cancelled,
+    Host(String),
+    /// The key of the setting
+    Unset(String),
+}
+
+pub type Result<T = (), E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::Io(_) => 1,
+            Self::Config(_) => 3,
+            Self::Git(_) => 4,
+            Self::Docker(_) => 5,
+            Self::Prompt(_) => 6,
+            Self::Cancelled => 7,
+            Self::Host(_) => 8,
+            Self::Unset(_) => 9,
+        }
+    }
+
+    pub fn exit_code(&self) -> ExitCode {
+        ExitCode::from(self.code())
+    }
+
+    /// Wrap a failure to write JSON that lila-docker built itself
+    pub fn json_output(err: serde_json::Error) -> Self {
+        Self::Io(err.into())
+    }
+
+    /// Wrap the error of an interactive prompt, which is how cliclack reports Ctrl-C and Esc.
+    pub fn prompt(err: std::io::Error) -> Self {
+        if err.kind() == ErrorKind::Interrupted {
+            Self::Cancelled
+        } else {
+            Self::Prompt(err)
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Config(message)
+            | Self::Git(message)
+            | Self::Docker(message)
+            | Self::Host(message) => {
+                write!(f, "{message}")
+            }
+            Self::Prompt(err) => write!(
+                f,
+                "Could not prompt for input: {err}\nRun from an interactive terminal, or pass an answers file with `--answers`"
+            ),
+            Self::Cancelled => write!(f, "Cancelled"),
+            Self::Unset(key) => write!(f, "`{key}` is not set"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Ctrl-C and Esc only reach prompts, which go through `Error::prompt`, so any other
+/// I/O failure is one.
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// JSON that fails to parse comes from a file lila-docker is configured with, such as
+/// `devcontainer.json`. Writing JSON goes through `Error::json_output` instead.
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Config(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let errors = [
+            Error::Io(std::io::Error::other("disk full")),
+            Error::Config(String::new()),
+            Error::Git(String::new()),
+            Error::Docker(String::new()),
+            Error::Prompt(std::io::Error::other("not a terminal")),
+            Error::Cancelled,
+            Error::Host(String::new()),
+            Error::Unset(String::new()),
+        ];
+        let codes: Vec<u8> = errors.iter().map(Error::code).collect();
+        for (i, code) in codes.iter().enumerate() {
+            assert!(!codes[i + 1..].contains(code), "{code} is used twice");
+        }
+    }
+
+    #[test]
+    fn test_json_errors_are_config_errors() {
+        let err = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        assert_eq!(Error::from(err).code(), 3);
+    }
+
+    #[test]
+    fn test_interrupted_prompt_is_cancelled() {
+        let interrupted = || std::io::Error::from(ErrorKind::Interrupted);
+        assert!(matches!(Error::from(interrupted()), Error::Io(_)));
+        assert!(matches!(Error::prompt(interrupted()), Error::Cancelled));
+        assert!(matches!(
+            Error::prompt(std::io::Error::other("not a terminal")),
+            Error::Prompt(_)
+        ));
+    }
+}
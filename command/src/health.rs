@@ -0,0 +1,253 @@
+use std::{
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    process::Command,
+    time::Duration,
+};
+
+use crate::{compose::PublishedPort, config::DEFAULT_LILA_PORT, services, Config};
+
+/// Something to poll until the service behind it answers
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Endpoint {
+    pub label: String,
+    /// The compose service to show the logs of if it never answers
+    pub service: String,
+    pub url: String,
+}
+
+/// lila, then every HTTP endpoint of the enabled services, on the host ports they are published on
+pub fn endpoints(config: &Config, published: &[PublishedPort]) -> Vec<Endpoint> {
+    let lila_port = config.lila_port();
+    let lila_url = config
+        .lila_url
+        .clone()
+        .unwrap_or_else(|| format!("http://localhost:{DEFAULT_LILA_PORT}"));
+    // An https `lila_url` is served by a proxy in front of lila-docker, such as a Codespace
+    let lila_url = if lila_url.starts_with("http://") {
+        lila_url
+    } else {
+        format!("http://localhost:{lila_port}")
+    };
+    let lila = if config.quick_setup.unwrap_or_default() {
+        "mono"
+    } else {
+        "lila"
+    };
+
+    let mut endpoints = vec![Endpoint {
+        label: "lila".to_string(),
+        service: lila.to_string(),
+        url: lila_url,
+    }];
+    let profiles = config.compose_profiles.clone().unwrap_or_default();
+    for service in services::enabled_services(&profiles) {
+        for port in &service.ports {
+            let Some(path) = port.health else {
+                continue;
+            };
+            let Some(published) = published
+                .iter()
+                .find(|published| published.default_host == port.number)
+            else {
+                continue;
+            };
+            endpoints.push(Endpoint {
+                label: port.label.to_string(),
+                service: published.service.clone(),
+                url: format!("http://localhost:{}{path}", published.host),
+            });
+        }
+    }
+    endpoints
+}
+
+/// The status code `url` answers a GET with
+pub fn probe(url: &str, timeout: Duration) -> Result<u16, String> {
+    let (host, port, path) = parse_url(url).ok_or_else(|| format!("cannot poll {url}"))?;
+    let address = (host, port)
+        .to_socket_addrs()
+        .map_err(|err| err.to_string())?
+        .next()
+        .ok_or_else(|| format!("{host} does not resolve"))?;
+    let mut stream =
+        TcpStream::connect_timeout(&address, timeout).map_err(|err| err.to_string())?;
+    stream
+        .set_read_timeout(Some(timeout))
+        .and_then(|()| stream.set_write_timeout(Some(timeout)))
+        .map_err(|err| err.to_string())?;
+    write!(
+        stream,
+        "GET {path} HTTP/1.1\r\nHost: {host}:{port}\r\nConnection: close\r\n\r\n"
+    )
+    .map_err(|err| err.to_string())?;
+
+    let mut head = [0; 64];
+    let read = stream.read(&mut head).map_err(|err| err.to_string())?;
+    status_code(&String::from_utf8_lossy(&head[..read]))
+        .ok_or_else(|| "not an HTTP response".to_string())
+}
+
+/// Whether a status code means the service is up and serving
+pub fn is_ready(status: u16) -> bool {
+    (200..400).contains(&status)
+}
+
+/// The host, port and path of a plain `http://` URL
+fn parse_url(url: &str) -> Option<(&str, u16, &str)> {
+    let rest = url.strip_prefix("http://")?;
+    let (authority, path) = rest.find('/').map_or((rest, "/"), |i| rest.split_at(i));
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().ok()?),
+        None => (authority, 80),
+    };
+    (!host.is_empty()).then_some((host, port, path))
+}
+
+/// The code in a status line such as `HTTP/1.1 502 Bad Gateway`
+fn status_code(response: &str) -> Option<u16> {
+    let mut parts = response.lines().next()?.split_whitespace();
+    parts
+        .next()
+        .filter(|version| version.starts_with("HTTP/"))?;
+    parts.next()?.parse().ok()
+}
+
+/// The last `lines` lines `service` logged, empty if Docker cannot tell
+pub fn last_logs(config: &Config, service: &str, lines: usize) -> String {
+    Command::new("docker")
+        .args(["compose", "logs", "--no-log-prefix", "--tail"])
+        .arg(lines.to_string())
+        .arg(service)
+        .envs(config.env())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| {
+            let mut logs = String::from_utf8_lossy(&output.stdout).to_string();
+            logs.push_str(&String::from_utf8_lossy(&output.stderr));
+            logs.trim_end().to_string()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::TcpListener, thread};
+
+    fn published(service: &str, host: u16, default_host: u16) -> PublishedPort {
+        PublishedPort {
+            service: service.to_string(),
+            container: default_host.to_string(),
+            host,
+            default_host,
+        }
+    }
+
+    #[test]
+    fn test_endpoints() {
+        let config = Config {
+            compose_profiles: Some(vec![
+                "base".to_string(),
+                "email".to_string(),
+                "gifs".to_string(),
+            ]),
+            lila_url: Some("http://192.168.1.2:8080".to_string()),
+            ..Default::default()
+        };
+        let ports = [
+            published("caddy", 8080, 8080),
+            published("mailpit", 8026, 8025),
+            published("lila_gif", 6175, 6175),
+        ];
+
+        let urls: Vec<_> = endpoints(&config, &ports)
+            .into_iter()
+            .map(|endpoint| (endpoint.service, endpoint.url))
+            .collect();
+        assert_eq!(
+            urls,
+            [
+                ("lila".to_string(), "http://192.168.1.2:8080".to_string()),
+                (
+                    "mailpit".to_string(),
+                    "http://localhost:8026/readyz".to_string()
+                ),
+                (
+                    "lila_gif".to_string(),
+                    "http://localhost:6175/image.gif?fen=4k3/6KP/8/8/8/8/7p/8".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_endpoints_proxied_lila() {
+        let config = Config {
+            quick_setup: Some(true),
+            lila_url: Some("https://example-8080.app.github.dev".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            endpoints(&config, &[]),
+            [Endpoint {
+                label: "lila".to_string(),
+                service: "mono".to_string(),
+                url: "http://localhost:8080".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(
+            parse_url("http://localhost:8080"),
+            Some(("localhost", 8080, "/"))
+        );
+        assert_eq!(
+            parse_url("http://localhost:9200/_cluster/health?timeout=1s"),
+            Some(("localhost", 9200, "/_cluster/health?timeout=1s"))
+        );
+        assert_eq!(
+            parse_url("http://lichess.test/"),
+            Some(("lichess.test", 80, "/"))
+        );
+        assert_eq!(parse_url("https://localhost:8080"), None);
+        assert_eq!(parse_url("http://localhost:http"), None);
+    }
+
+    #[test]
+    fn test_status_code() {
+        assert_eq!(status_code("HTTP/1.1 200 OK\r\nServer: x\r\n"), Some(200));
+        assert_eq!(status_code("HTTP/1.0 502 Bad Gateway"), Some(502));
+        assert_eq!(status_code("SSH-2.0-OpenSSH_9.6"), None);
+        assert_eq!(status_code(""), None);
+        assert!(is_ready(200));
+        assert!(is_ready(302));
+        assert!(!is_ready(404));
+        assert!(!is_ready(502));
+    }
+
+    #[test]
+    fn test_probe() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 256];
+            let read = stream.read(&mut request).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 503 Service Unavailable\r\n\r\n")
+                .unwrap();
+            String::from_utf8_lossy(&request[..read]).to_string()
+        });
+
+        let url = format!("http://127.0.0.1:{port}/readyz");
+        assert_eq!(probe(&url, Duration::from_secs(5)), Ok(503));
+        assert!(server
+            .join()
+            .unwrap()
+            .starts_with("GET /readyz HTTP/1.1\r\n"));
+    }
+}
@@ -1,19 +1,44 @@
 #![warn(clippy::pedantic)]
 
+use answers::Answers;
+use clap::{Parser, Subcommand};
 use cliclack::{
     confirm, input, intro,
     log::{info, success, warning},
-    multiselect, note, outro, select, spinner,
+    multi_progress, multiselect, note, outro, select, spinner,
 };
+use config::Config;
+use error::{Error, Result};
 use local_ip_address::local_ip;
-use serde::{Deserialize, Serialize};
+use repository::{Checkout, History, Repository};
+use resources::{HostLimits, Resources};
+use serde::Deserialize;
+use services::OptionalService;
 use std::{
     format,
-    io::Error,
+    num::{NonZeroU32, NonZeroU64, NonZeroUsize},
     path::{Path, PathBuf},
-    process::Command,
+    process::ExitCode,
+    thread,
+    time::{Duration, Instant},
 };
 
+mod answers;
+mod compose;
+mod config;
+mod devcontainer;
+mod docker;
+mod doctor;
+mod dotenv;
+mod error;
+mod health;
+mod ports;
+mod repository;
+mod resources;
+mod review;
+mod services;
+mod urls;
+
 const BANNER: &str = r"
    |\_    _ _      _
    /o \  | (_) ___| |__   ___  ___ ___   ___  _ __ __ _
@@ -25,179 +50,765 @@ const BANNER: &str = r"
 
 const DEFAULT_PASSWORD: &str = "password";
 
-#[derive(Serialize, Deserialize, Debug, Default)]
-struct Config {
-    quick_setup: Option<bool>,
-    compose_profiles: Option<Vec<String>>,
-    lila_ws_container: Option<String>,
-    setup_database: Option<bool>,
-    setup_bbppairings: Option<bool>,
-    mock_email: Option<bool>,
-    enable_monitoring: Option<bool>,
-    enable_rate_limiting: Option<bool>,
-    su_password: Option<String>,
-    password: Option<String>,
-    setup_api_tokens: Option<bool>,
-    lila_domain: Option<String>,
-    lila_url: Option<String>,
-}
-
-macro_rules! to_env {
-    ($name_opt:ident) => {
-        $name_opt
-            .clone()
-            .map(|v| format!("{}={}", stringify!($name_opt).to_uppercase(), v.to_string()))
-            .unwrap_or_default()
-    };
-    ($key:ident, $value:expr) => {
-        format!("{}={}", stringify!($key).to_uppercase(), $value)
-    };
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Setting {
+    SetupDatabase,
+    EnableRateLimiting,
 }
 
-impl Config {
-    const SETTINGS_TOML: &'static str = "settings.toml";
-    const SETTINGS_ENV: &'static str = "settings.env";
+impl Setting {
+    fn label(&self) -> &'static str {
+        match self {
+            Self::SetupDatabase => "database seeding",
+            Self::EnableRateLimiting => "rate limiting",
+        }
+    }
+}
 
-    fn load() -> Self {
-        std::fs::read_to_string(Self::SETTINGS_TOML).map_or_else(
-            |_| Self::default(),
-            |contents| toml::from_str(&contents).unwrap(),
-        )
+#[derive(Parser, Debug)]
+#[command(about = "Set up and manage a lila-docker development environment")]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Run the first-time setup wizard
+    Setup {
+        /// Skip all prompts and use the Quick setup defaults
+        #[arg(long, env = "NONINTERACTIVE", conflicts_with = "answers")]
+        noninteractive: bool,
+        /// Answer every prompt from a TOML file instead of interactively
+        #[arg(long, env = "LILA_DOCKER_ANSWERS", value_name = "FILE")]
+        answers: Option<PathBuf>,
+        /// How many repositories to clone at once
+        #[arg(long, env = "LILA_DOCKER_CLONE_JOBS", default_value = "4")]
+        jobs: NonZeroUsize,
+    },
+    /// Add new optional services to an existing Advanced setup
+    #[command(alias = "add_services")]
+    AddServices {
+        /// Answer every prompt from a TOML file instead of interactively
+        #[arg(long, env = "LILA_DOCKER_ANSWERS", value_name = "FILE")]
+        answers: Option<PathBuf>,
+        /// How many repositories to clone at once
+        #[arg(long, env = "LILA_DOCKER_CLONE_JOBS", default_value = "4")]
+        jobs: NonZeroUsize,
+    },
+    /// Choose the hostname used to access the local Lichess instance
+    Hostname,
+    /// Print the post-setup welcome message
+    Welcome {
+        /// Wait until lila and the selected services answer before printing it
+        #[arg(long)]
+        wait: bool,
+        /// How many minutes to wait for, since the first lila build can take a while
+        #[arg(long, value_name = "MINUTES", default_value = "20", requires = "wait")]
+        timeout: NonZeroU64,
+    },
+    /// List the URLs of lila and the selected services
+    Urls {
+        /// Print the URLs as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Inspect and change the settings in settings.toml
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Check out a pull request into one of ./repos and rebuild what it affects
+    Review {
+        /// The repository, e.g. `lila` or `lila-ws`
+        project: String,
+        /// The pull request number on GitHub
+        number: u32,
+        /// Only check out the pull request, without restarting or rebuilding anything
+        #[arg(long)]
+        no_rebuild: bool,
+    },
+    /// Check or update the git checkouts of lila-docker and ./repos
+    Repos {
+        #[command(subcommand)]
+        command: ReposCommand,
+    },
+    /// Detect the container runtime, save it in settings.toml and write the user the containers run as to .env
+    DetectRuntime,
+    /// Check the host for everything lila-docker needs, and suggest fixes for any problems
+    Doctor {
+        /// Print the results as JSON, e.g. for a bug report
+        #[arg(long)]
+        json: bool,
+    },
+    /// Regenerate the Codespaces port labels and lila URL in .devcontainer/devcontainer.json from the service catalog
+    Devcontainer,
+    /// Check that the host ports of the enabled services are free, and offer other ports for those that are taken
+    Ports {
+        /// Pick the suggested ports without prompting
+        #[arg(long, env = "NONINTERACTIVE")]
+        noninteractive: bool,
+        /// Recreate the containers whose ports changed
+        #[arg(long)]
+        recreate: bool,
+    },
+    /// Keep several branches of a repository checked out and choose which one the containers use
+    Worktree {
+        #[command(subcommand)]
+        command: WorktreeCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Validate settings.toml and suggest fixes for any problems
+    Check,
+    /// Print the value of a setting
+    Get { key: String },
+    /// Change one or more settings, e.g. `config set mock_email false`
+    ///
+    /// Settings that must agree with each other, such as `lila_domain` and `lila_url`,
+    /// can be changed together: `config set lila_domain foo:8080 lila_url http://foo:8080`
+    Set {
+        #[arg(value_name = "KEY VALUE", num_args = 2.., required = true)]
+        pairs: Vec<String>,
+    },
+    /// Remove one or more settings, reverting them to their defaults
+    Unset {
+        #[arg(required = true)]
+        keys: Vec<String>,
+    },
+    /// Print every setting that is set
+    List,
+}
+
+#[derive(Subcommand, Debug)]
+enum ReposCommand {
+    /// Fetch every repository and show its branch, how far it is ahead/behind and whether it is dirty
+    Status {
+        /// Print the results as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Fast-forward every clean repository that is on its main branch
+    Pull {
+        /// Print the results as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Fetch more history into shallow clones, all of it unless `--depth` or `--since` is given
+    Deepen {
+        /// Repositories to deepen, e.g. `lila scalachess`. Defaults to all of them
+        projects: Vec<String>,
+        /// Fetch only the last N commits
+        #[arg(long, conflicts_with = "since")]
+        depth: Option<NonZeroU32>,
+        /// Fetch the commits after a date, e.g. `2024-01-01`
+        #[arg(long, value_name = "DATE")]
+        since: Option<String>,
+        /// Also clone these repositories with their full history in future setups
+        #[arg(long, conflicts_with_all = ["depth", "since"])]
+        save: bool,
+    },
+    /// List the Git LFS files that were not downloaded when cloning
+    Lfs {
+        /// Repositories to check, e.g. `lila`. Defaults to all of them
+        projects: Vec<String>,
+        /// Download the missing files, which needs Git LFS to be installed
+        #[arg(long)]
+        fetch: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum WorktreeCommand {
+    /// Check out a branch into a new worktree, creating the branch if it does not exist
+    Add {
+        /// The repository, e.g. `lila` or `lila-ws`
+        project: String,
+        branch: String,
+        /// Where to create it. Defaults to `worktrees/<project>/<branch>`
+        #[arg(long)]
+        path: Option<PathBuf>,
+        /// Also mount the new worktree into the containers
+        #[arg(long = "use")]
+        activate: bool,
+    },
+    /// List the worktrees of every repository and which one is mounted
+    List,
+    /// Mount a worktree into the containers, or `main` for ./repos/<project>
+    Use {
+        project: String,
+        /// A branch checked out in a worktree, a worktree path, or `main`
+        worktree: String,
+    },
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            // If even this cannot be written there is nowhere left to report it
+            let _ = if let Error::Cancelled = err {
+                cliclack::outro_cancel(&err)
+            } else {
+                cliclack::log::error(&err)
+            };
+            err.exit_code()
+        }
     }
+}
 
-    fn save(&self) -> std::io::Result<()> {
-        std::fs::write(Self::SETTINGS_TOML, self.to_toml())?;
-        std::fs::write(Self::SETTINGS_ENV, self.to_env())
-    }
-
-    fn to_toml(&self) -> String {
-        toml::to_string(&self).unwrap()
-    }
-
-    fn to_env(&self) -> String {
-        let Self {
-            quick_setup,
-            compose_profiles,
-            lila_ws_container,
-            setup_database,
-            setup_bbppairings,
-            mock_email,
-            enable_monitoring,
-            enable_rate_limiting,
-            su_password,
-            password,
-            setup_api_tokens,
-            lila_domain,
-            lila_url,
-        } = self;
-        let compose_profiles_string = compose_profiles
-            .clone()
-            .map(|v| v.join(","))
-            .unwrap_or_default();
-
-        vec![
-            to_env!(quick_setup),
-            to_env!(compose_profiles, compose_profiles_string),
-            to_env!(lila_ws_container),
-            to_env!(setup_database),
-            to_env!(setup_bbppairings),
-            to_env!(mock_email),
-            to_env!(enable_monitoring),
-            to_env!(enable_rate_limiting),
-            to_env!(su_password),
-            to_env!(password),
-            to_env!(setup_api_tokens),
-            to_env!(lila_domain),
-            to_env!(lila_url),
-        ]
-        .iter()
-        .filter(|line| !line.is_empty())
-        .map(std::string::ToString::to_string)
-        .collect::<Vec<String>>()
-        .join("\n")
+fn run(cli: Cli) -> Result {
+    match cli.command {
+        Commands::Setup {
+            noninteractive,
+            answers,
+            jobs,
+        } => {
+            let config = Config::load()?;
+            let answers = answers.as_deref().map(Answers::load).transpose()?;
+            setup(config, true, noninteractive, answers.as_ref(), jobs)
+        }
+        Commands::AddServices { answers, jobs } => {
+            let config = Config::load()?;
+            if config.quick_setup.unwrap_or(false) {
+                return Err(Error::Config(
+                    "Cannot add services to a quick/mono setup. Re-run setup (`./lila-docker down && ./lila-docker start`) and switch to advanced mode.".to_string(),
+                ));
+            }
+            let answers = answers.as_deref().map(Answers::load).transpose()?;
+            setup(config, false, false, answers.as_ref(), jobs)
+        }
+        Commands::Hostname => hostname(Config::load()?),
+        Commands::Welcome { wait, timeout } => welcome(Config::load()?, wait.then_some(timeout)),
+        Commands::Urls { json } => list_urls(&Config::load()?, json),
+        // `config` subcommands report validation errors themselves
+        Commands::Config { command } => {
+            let config = Config::load_unvalidated()?;
+            match command {
+                ConfigCommand::Check => config_check(&config),
+                ConfigCommand::Get { key } => config_get(&config, &key),
+                ConfigCommand::Set { pairs } => config_set(config, &pairs),
+                ConfigCommand::Unset { keys } => config_unset(config, &keys),
+                ConfigCommand::List => {
+                    config_list(&config);
+                    Ok(())
+                }
+            }
+        }
+        Commands::Review {
+            project,
+            number,
+            no_rebuild,
+        } => review(&Config::load()?, &project, number, !no_rebuild),
+        Commands::Repos { command } => {
+            let config = Config::load()?;
+            match command {
+                ReposCommand::Status { json } => repos_status(false, json),
+                ReposCommand::Pull { json } => repos_status(true, json),
+                ReposCommand::Deepen {
+                    projects,
+                    depth,
+                    since,
+                    save,
+                } => {
+                    let history = match (depth, since) {
+                        (Some(depth), _) => History::Depth(depth.get()),
+                        (None, Some(date)) => History::Since(date),
+                        (None, None) => History::Full,
+                    };
+                    repos_deepen(config, &projects, &history, save)
+                }
+                ReposCommand::Lfs { projects, fetch } => repos_lfs(&projects, fetch),
+            }
+        }
+        Commands::DetectRuntime => detect_runtime(Config::load()?),
+        // `doctor` reports a broken settings.toml as one of its findings
+        Commands::Doctor { json } => doctor(Config::load_unvalidated(), json),
+        Commands::Devcontainer => update_devcontainer(),
+        Commands::Ports {
+            noninteractive,
+            recreate,
+        } => check_ports(Config::load()?, noninteractive, recreate),
+        Commands::Worktree { command } => {
+            let config = Config::load()?;
+            match command {
+                WorktreeCommand::Add {
+                    project,
+                    branch,
+                    path,
+                    activate,
+                } => worktree_add(config, &project, &branch, path, activate),
+                WorktreeCommand::List => worktree_list(&config),
+                WorktreeCommand::Use { project, worktree } => {
+                    worktree_use(config, &project, &worktree)
+                }
+            }
+        }
     }
 }
 
-#[derive(Clone, Eq, PartialEq, Debug)]
-struct Repository {
-    org: String,
-    project: String,
+fn repos_status(pull: bool, json: bool) -> Result {
+    let checkouts = repository::checkouts(Path::new("."))?;
+
+    let statuses = if json {
+        repository::statuses(&checkouts, pull)
+    } else {
+        let progress = spinner();
+        progress.start(format!(
+            "{} {} repositories...",
+            if pull { "Pulling" } else { "Fetching" },
+            checkouts.len()
+        ));
+        let statuses = repository::statuses(&checkouts, pull);
+        progress.stop(format!("✓ Checked {} repositories", statuses.len()));
+        statuses
+    };
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&statuses).map_err(Error::json_output)?
+        );
+    } else {
+        print!("{}", repository::table(&statuses));
+    }
+
+    let failed = statuses
+        .iter()
+        .filter(|status| status.outcome == repository::Outcome::Failed)
+        .count();
+    if failed > 0 {
+        return Err(Error::Git(format!(
+            "{failed} of {} repositories failed",
+            statuses.len()
+        )));
+    }
+    Ok(())
 }
 
-impl Repository {
-    fn new(org: &str, project: &str) -> Self {
-        Self {
-            org: org.to_string(),
-            project: project.to_string(),
+fn review(config: &Config, project: &str, number: u32, rebuild: bool) -> Result {
+    if config.quick_setup.unwrap_or(false) {
+        return Err(Error::Config(
+            "Pull requests can only be reviewed in an Advanced setup, the Quick setup does not use ./repos".to_string(),
+        ));
+    }
+    let repo = find_repository(config, project)?;
+
+    let progress = spinner();
+    progress.start(format!(
+        "Fetching pull request #{number} of {}...",
+        repo.full_name()
+    ));
+    let pull_request = match repo.checkout_pull_request(number) {
+        Ok(pull_request) => pull_request,
+        Err(err) => {
+            progress.error(format!("✗ Could not check out pull request #{number}"));
+            return Err(err);
+        }
+    };
+    progress.stop(format!(
+        "✓ Switched {} to branch {} ({} files changed)",
+        repo.active_path().display(),
+        pull_request.branch,
+        pull_request.changed.len()
+    ));
+
+    let rebuilds = review::rebuilds(&repo.project, &pull_request.changed, config);
+    if rebuilds.is_empty() && repo.project == "lila-ws" && !pull_request.changed.is_empty() {
+        warning("lila-ws runs from its prebuilt image, so the changes have no effect.\nAdd the Websocket source code service with `./lila-docker add-services` to run it from ./repos/lila-ws")?;
+    } else if rebuilds.is_empty() {
+        info("Nothing to rebuild")?;
+    } else if !rebuild {
+        info(format!(
+            "Skipping the {}",
+            rebuilds
+                .iter()
+                .map(|rebuild| rebuild.label())
+                .collect::<Vec<_>>()
+                .join(" and ")
+        ))?;
+    } else {
+        for rebuild in rebuilds {
+            info(format!("Running the {}...", rebuild.label()))?;
+            rebuild.run(config)?;
         }
     }
 
-    fn full_name(&self) -> String {
-        format!("{}/{}", self.org, self.project)
+    outro(format!(
+        "Reviewing pull request #{number} of {}",
+        repo.full_name()
+    ))?;
+    Ok(())
+}
+
+/// The repository named `project` with its settings from `config` applied
+fn find_repository(config: &Config, project: &str) -> Result<Repository> {
+    let repos = services::all_repositories();
+    repos
+        .iter()
+        .find(|repo| repo.project == project)
+        .map(|repo| config.repository(repo))
+        .ok_or_else(|| {
+            Error::Config(format!(
+                "`{project}` is not a repository lila-docker clones, expected one of: {}",
+                repos
+                    .iter()
+                    .map(|repo| repo.project.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+        })
+}
+
+/// `path` relative to the lila-docker directory when it is inside it, as compose mounts expect
+fn relative_to_root(path: &Path) -> Result<PathBuf> {
+    let root = std::env::current_dir()?;
+    let path = std::path::absolute(path)?;
+    Ok(path
+        .strip_prefix(&root)
+        .map_or_else(|_| path.clone(), Path::to_path_buf))
+}
+
+fn worktree_add(
+    config: Config,
+    project: &str,
+    branch: &str,
+    path: Option<PathBuf>,
+    activate: bool,
+) -> Result {
+    let repo = find_repository(&config, project)?;
+    if !repository::is_clone(&repo.clone_path()) {
+        return Err(Error::Git(format!(
+            "{} is not cloned, select a service that uses it with `./lila-docker add-services`",
+            repo.clone_path().display()
+        )));
+    }
+    let path = path.unwrap_or_else(|| Path::new("worktrees").join(project).join(branch));
+    if path.exists() {
+        return Err(Error::Config(format!(
+            "{} already exists, choose another `--path`",
+            path.display()
+        )));
     }
 
-    fn url(&self) -> String {
-        format!("https://github.com/{}/{}", self.org, self.project)
+    repository::add_worktree(&repo.clone_path(), &path, branch)?;
+    success(format!("✓ Checked out {branch} into {}", path.display()))?;
+
+    if activate {
+        activate_worktree(config, project, Some(relative_to_root(&path)?))
+    } else {
+        info(format!(
+            "Run `./lila-docker worktree use {project} {branch}` to mount it into the containers"
+        ))?;
+        Ok(())
+    }
+}
+
+fn worktree_list(config: &Config) -> Result {
+    let mut listed = false;
+    for (project, path) in repository::clones(Path::new("."))? {
+        let worktrees = repository::worktrees(&path)?;
+        if worktrees.len() < 2 {
+            continue;
+        }
+        listed = true;
+        // git reports canonical paths
+        let active = config
+            .worktree(&project)
+            .and_then(|active| std::fs::canonicalize(active).ok());
+        let lines: Vec<String> = worktrees
+            .iter()
+            .enumerate()
+            .map(|(i, worktree)| {
+                let is_active = match &active {
+                    Some(active) => *active == worktree.path,
+                    None => i == 0,
+                };
+                format!(
+                    "{} {} ({})",
+                    if is_active { "*" } else { " " },
+                    relative_to_root(&worktree.path)
+                        .unwrap_or_else(|_| worktree.path.clone())
+                        .display(),
+                    worktree.branch.as_deref().unwrap_or("detached")
+                )
+            })
+            .collect();
+        note(project, lines.join("\n"))?;
+    }
+    if !listed {
+        info("No repository has worktrees yet, add one with `./lila-docker worktree add <project> <branch>`")?;
     }
+    Ok(())
+}
 
-    fn clone_path(&self) -> PathBuf {
-        Path::new("repos").join(&self.project)
+fn worktree_use(config: Config, project: &str, worktree: &str) -> Result {
+    let repo = find_repository(&config, project)?;
+    if worktree == "main" {
+        return activate_worktree(config, project, None);
     }
+
+    let worktrees = repository::worktrees(&repo.clone_path())?;
+    let path = std::fs::canonicalize(worktree).ok();
+    let Some(found) = worktrees.iter().skip(1).find(|found| {
+        path.as_ref() == Some(&found.path) || found.branch.as_deref() == Some(worktree)
+    }) else {
+        return Err(Error::Config(format!(
+            "`{worktree}` is not a worktree of {project}, add it with `./lila-docker worktree add {project} {worktree}`"
+        )));
+    };
+    let path = relative_to_root(&found.path)?;
+    activate_worktree(config, project, Some(path))
 }
 
-#[derive(Default, Clone, Eq, PartialEq, Debug)]
-struct OptionalService<'a> {
-    compose_profile: Option<Vec<&'a str>>,
-    repositories: Option<Vec<Repository>>,
+/// Save `path` as the worktree of `project` to mount, `None` for `repos/<project>`
+fn activate_worktree(mut config: Config, project: &str, path: Option<PathBuf>) -> Result {
+    let mounted = path.as_deref().map_or_else(
+        || format!("repos/{project}"),
+        |path| path.display().to_string(),
+    );
+    let repositories = config.repositories.get_or_insert_with(Default::default);
+    repositories
+        .entry(project.to_string())
+        .or_default()
+        .worktree = path.map(|path| path.to_string_lossy().into_owned());
+    repositories.retain(|_, settings| *settings != config::RepositorySettings::default());
+    if repositories.is_empty() {
+        config.repositories = None;
+    }
+    config.save()?;
+
+    success(format!("✓ {project} now mounts {mounted}"))?;
+    info("Run `docker compose up -d` to recreate the containers with it")?;
+    Ok(())
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
-enum Setting {
-    SetupDatabase,
-    EnableRateLimiting,
+/// The clones in `./repos` named by `projects`, or all of them
+fn select_clones(projects: &[String]) -> Result<Vec<(String, PathBuf)>> {
+    let checkouts = repository::clones(Path::new("."))?;
+    if projects.is_empty() {
+        return Ok(checkouts);
+    }
+    projects
+        .iter()
+        .map(|project| {
+            checkouts
+                .iter()
+                .find(|(name, _)| name == project)
+                .cloned()
+                .ok_or_else(|| {
+                    Error::Config(format!(
+                        "`{project}` is not cloned, expected one of: {}",
+                        checkouts
+                            .iter()
+                            .map(|(name, _)| name.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ))
+                })
+        })
+        .collect()
 }
 
-fn main() -> std::io::Result<()> {
-    let args: Vec<String> = std::env::args().collect();
-    assert!(args.len() > 1, "Missing command");
+fn repos_deepen(mut config: Config, projects: &[String], history: &History, save: bool) -> Result {
+    let selected = select_clones(projects)?;
+
+    let progress = spinner();
+    progress.start(format!(
+        "Fetching history of {} repositories...",
+        selected.len()
+    ));
+    let jobs = NonZeroUsize::new(selected.len()).unwrap_or(NonZeroUsize::MIN);
+    let results = repository::parallel(&selected, jobs, |(_, path)| {
+        repository::deepen(path, history)
+    });
+    progress.stop(format!(
+        "✓ Fetched history of {} repositories",
+        selected.len()
+    ));
+
+    let mut failed = 0;
+    let mut deepened = vec![];
+    for ((name, _), result) in selected.iter().zip(results) {
+        match result {
+            Ok(summary) => {
+                success(format!("{name}: {summary}"))?;
+                deepened.push(name);
+            }
+            Err(err) => {
+                failed += 1;
+                cliclack::log::error(format!("{name}: {err}"))?;
+            }
+        }
+    }
 
-    let config = Config::load();
+    let projects: Vec<String> = services::all_repositories()
+        .into_iter()
+        .map(|repo| repo.project)
+        .filter(|project| deepened.contains(&project))
+        .collect();
+    if save && !projects.is_empty() {
+        let repositories = config.repositories.get_or_insert_with(Default::default);
+        for project in &projects {
+            let settings = repositories.entry(project.clone()).or_default();
+            settings.full_history = Some(true);
+            settings.depth = None;
+        }
+        config.save()?;
+        success(format!(
+            "✓ Future setups will clone {} with their full history",
+            projects.join(", ")
+        ))?;
+    }
 
-    match args[1].as_str() {
-        "setup" => setup(config, true, std::env::var("NONINTERACTIVE").is_ok()),
-        "add_services" => {
-            if config.quick_setup.unwrap_or(false) {
-                cliclack::log::error(
-                    "Cannot add services to a quick/mono setup. Re-run setup (`./lila-docker down && ./lila-docker start`) and switch to advanced mode.",
-                )?;
-                std::process::exit(1);
+    if failed > 0 {
+        return Err(Error::Git(format!(
+            "{failed} of {} repositories failed",
+            selected.len()
+        )));
+    }
+    Ok(())
+}
+
+fn repos_lfs(projects: &[String], fetch: bool) -> Result {
+    let selected = select_clones(projects)?;
+
+    if fetch {
+        for (name, path) in &selected {
+            if repository::lfs_pointers(path)?.is_empty() {
+                continue;
+            }
+            let progress = spinner();
+            progress.start(format!("Downloading LFS files of {name}..."));
+            match repository::lfs_pull(path) {
+                Ok(()) => progress.stop(format!("✓ Downloaded LFS files of {name}")),
+                Err(err) => {
+                    progress.error(format!("✗ Failed to download LFS files of {name}"));
+                    return Err(err);
+                }
             }
-            setup(config, false, false)
         }
-        "hostname" => hostname(config),
-        "welcome" => welcome(config),
-        _ => panic!("Unknown command"),
     }
+
+    let mut missing = 0;
+    for (name, path) in &selected {
+        let pointers = repository::lfs_pointers(path)?;
+        if pointers.is_empty() {
+            continue;
+        }
+        missing += pointers.len();
+        note(
+            format!("{name}: {} LFS files not downloaded", pointers.len()),
+            pointers
+                .iter()
+                .map(|pointer| pointer.display().to_string())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )?;
+    }
+
+    if missing == 0 {
+        success("✓ Every LFS file is downloaded")?;
+    } else if !fetch {
+        info(if repository::has_git_lfs() {
+            "Run `./lila-docker lfs --fetch` to download them"
+        } else {
+            "Install Git LFS from https://git-lfs.com/, then run `./lila-docker lfs --fetch` to download them"
+        })?;
+    }
+    Ok(())
+}
+
+fn config_check(config: &Config) -> Result {
+    let mut errors = config.validate().err().unwrap_or_default();
+    errors.extend(config.check_env_file().err());
+
+    if errors.is_empty() {
+        success("✓ settings.toml is valid")?;
+        return Ok(());
+    }
+    for error in &errors {
+        cliclack::log::error(error)?;
+    }
+    Err(Error::Config(format!(
+        "Found {} problem(s) in {}",
+        errors.len(),
+        Config::SETTINGS_TOML
+    )))
+}
+
+fn config_get(config: &Config, key: &str) -> Result {
+    match config.get(key).map_err(Error::Config)? {
+        Some(value) => {
+            println!("{}", config::display_value(&value));
+            Ok(())
+        }
+        None => Err(Error::Unset(key.to_string())),
+    }
+}
+
+fn config_set(mut config: Config, pairs: &[String]) -> Result {
+    if !pairs.len().is_multiple_of(2) {
+        return Err(Error::Config(format!(
+            "Missing a value for `{}`",
+            pairs[pairs.len() - 1]
+        )));
+    }
+    for pair in pairs.chunks(2) {
+        config.set(&pair[0], &pair[1]).map_err(Error::Config)?;
+    }
+    config.save()?;
+    success(format!("✓ Updated {}", Config::SETTINGS_TOML))?;
+    Ok(())
+}
+
+fn config_unset(mut config: Config, keys: &[String]) -> Result {
+    for key in keys {
+        config.unset(key).map_err(Error::Config)?;
+    }
+    config.save()?;
+    success(format!("✓ Updated {}", Config::SETTINGS_TOML))?;
+    Ok(())
 }
 
-fn pwd_input(user_type: &str) -> std::io::Result<String> {
+fn config_list(config: &Config) {
+    for (key, value) in config.entries() {
+        println!("{key} = {}", config::display_value(&value));
+    }
+}
+
+fn pwd_input(user_type: &str) -> Result<String> {
     input(format!(
         "Choose a password for {user_type} users (blank for 'password')"
     ))
     .placeholder(DEFAULT_PASSWORD)
     .default_input(DEFAULT_PASSWORD)
     .required(false)
+    .validate(|password: &String| config::check_password(password))
     .interact()
+    .map_err(Error::prompt)
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
 enum SetupMethod {
     Quick,
     Advanced,
 }
 
 #[allow(clippy::too_many_lines)]
-fn setup(mut config: Config, first_setup: bool, noninteractive: bool) -> std::io::Result<()> {
+fn setup(
+    mut config: Config,
+    first_setup: bool,
+    noninteractive: bool,
+    answers: Option<&Answers>,
+    clone_jobs: NonZeroUsize,
+) -> Result {
     if first_setup {
         intro(BANNER)?;
     } else {
@@ -207,7 +818,7 @@ fn setup(mut config: Config, first_setup: bool, noninteractive: bool) -> std::io
         )?;
     }
 
-    let mut services: Vec<OptionalService<'static>> = vec![];
+    let mut services: Vec<OptionalService> = vec![];
 
     info(
         [
@@ -218,19 +829,23 @@ fn setup(mut config: Config, first_setup: bool, noninteractive: bool) -> std::io
         .join("\n"),
     )?;
     let is_quick_setup = noninteractive
-        || select("Choose a setup method:")
-            .item(
-                SetupMethod::Quick,
-                "Quick",
-                "If you just want a basic lila instance without making any code changes",
-            )
-            .item(
-                SetupMethod::Advanced,
-                "Advanced",
-                "If you want to make changes or test specific features",
-            )
-            .interact()?
-            == SetupMethod::Quick;
+        || if let Some(answers) = answers {
+            Answers::require("setup_method", answers.setup_method.as_ref())?
+        } else {
+            select("Choose a setup method:")
+                .item(
+                    SetupMethod::Quick,
+                    "Quick",
+                    "If you just want a basic lila instance without making any code changes",
+                )
+                .item(
+                    SetupMethod::Advanced,
+                    "Advanced",
+                    "If you want to make changes or test specific features",
+                )
+                .interact()
+                .map_err(Error::prompt)?
+        } == SetupMethod::Quick;
     config.quick_setup = Some(is_quick_setup);
 
     if noninteractive || is_quick_setup {
@@ -240,28 +855,51 @@ fn setup(mut config: Config, first_setup: bool, noninteractive: bool) -> std::io
         config.enable_rate_limiting = Some(true);
         config.setup_database = Some(true);
     } else {
-        if has_git_lfs() {
-            success("✓ Git LFS is installed")?;
-        } else {
-            assert!(confirm(
-                [
-                    "Git LFS is not installed. It is used to manage large files in Git repositories.",
-                    "Some optional features may not work without it.",
-                    "You can read about it at https://git-lfs.com/",
-                    "Do you want to continue anyway?",
-                ]
-                .join("\n"),
-            )
-            .initial_value(true)
-            .interact()?, "Cancelled setup");
+        let resolution = services::resolve(&services::catalog(), &prompt_for_services(answers)?);
+        for (_, reason) in &resolution.added {
+            info(format!("Also enabling {reason}"))?;
+        }
+        if !resolution.conflicts.is_empty() {
+            warning(resolution.conflicts.join("\n"))?;
         }
+        services = resolution.services;
 
-        services = prompt_for_services()?;
+        let options = prompt_for_options(first_setup, answers)?;
+        for reason in services::missing_settings(&services, &options) {
+            warning(reason)?;
+        }
 
-        let options = prompt_for_options(first_setup)?;
+        // lila itself is only cloned, and so only missing its LFS files, on the first setup
+        let mut new_profiles: Vec<String> = services
+            .iter()
+            .flat_map(|service| service.compose_profiles.iter())
+            .map(ToString::to_string)
+            .collect();
+        if first_setup {
+            new_profiles.push("base".to_string());
+        }
+        check_git_lfs(&new_profiles, answers)?;
+
+        let mut running = services.clone();
+        if !first_setup {
+            let existing = config.compose_profiles.clone().unwrap_or_default();
+            running.extend(
+                services::enabled_services(&existing)
+                    .into_iter()
+                    .filter(|service| !services.contains(service)),
+            );
+        }
+        check_resource_budget(&running, answers)?;
 
         let (su_password, password) = if options.contains(&Setting::SetupDatabase) {
-            (pwd_input("admin")?, pwd_input("regular")?)
+            if let Some(answers) = answers {
+                (
+                    Answers::require("su_password", answers.su_password.as_ref())?,
+                    Answers::require("password", answers.password.as_ref())?,
+                )
+            } else {
+                (pwd_input("admin")?, pwd_input("regular")?)
+            }
         } else {
             (DEFAULT_PASSWORD.to_string(), DEFAULT_PASSWORD.to_string())
         };
@@ -269,8 +907,13 @@ fn setup(mut config: Config, first_setup: bool, noninteractive: bool) -> std::io
         config.setup_api_tokens = Some(
             options.contains(&Setting::SetupDatabase)
                 && if password != "password" || su_password != "password" {
-                    confirm("Do you want to setup default API tokens for the admin and regular users? Will be created with `lip_{username}` format")
-            .interact()?
+                    if let Some(answers) = answers {
+                        Answers::require("setup_api_tokens", answers.setup_api_tokens.as_ref())?
+                    } else {
+                        confirm("Do you want to setup default API tokens for the admin and regular users? Will be created with `lip_{username}` format")
+                            .interact()
+                            .map_err(Error::prompt)?
+                    }
                 } else {
                     true
                 },
@@ -281,40 +924,12 @@ fn setup(mut config: Config, first_setup: bool, noninteractive: bool) -> std::io
         config.su_password = Some(su_password);
         config.password = Some(password);
 
-        config.setup_bbppairings = Some(
-            services
-                .iter()
-                .any(|service| service.compose_profile == Some(vec!["swiss-pairings"])),
-        );
-
-        config.mock_email = Some(
-            !services
-                .iter()
-                .any(|service| service.compose_profile == Some(vec!["email"])),
-        );
-
-        config.enable_monitoring = Some(
-            services
-                .iter()
-                .any(|service| service.compose_profile == Some(vec!["monitoring"])),
-        );
-
-        config.lila_ws_container = Some(
-            if services
-                .iter()
-                .any(|service| service.compose_profile == Some(vec!["lila-ws-build"]))
-            {
-                "build".to_string()
-            } else {
-                "image".to_string()
-            },
-        );
+        services::apply_implications(&mut config, &services);
     }
 
     let selected_profiles: Vec<String> = services
         .iter()
-        .filter_map(|service| service.compose_profile.as_ref())
-        .flatten()
+        .flat_map(|service| service.compose_profiles.iter())
         .map(ToString::to_string)
         .collect();
 
@@ -330,233 +945,365 @@ fn setup(mut config: Config, first_setup: bool, noninteractive: bool) -> std::io
     profiles.sort();
     profiles.dedup();
 
+    if let Err(errors) = services::validate_profiles(&profiles) {
+        return Err(Error::Config(format!(
+            "The selected services cannot run together:\n{}",
+            errors.join("\n")
+        )));
+    }
+
     config.compose_profiles = Some(profiles);
+    resolve_port_conflicts(&mut config, noninteractive, answers)?;
 
     config.save()?;
 
     if !is_quick_setup {
-        create_placeholder_dirs();
+        create_placeholder_dirs()?;
 
-        let mut repos_to_clone: Vec<Repository> = vec![Repository::new("lichess-org", "lila")];
+        let mut repos_to_clone: Vec<Repository> =
+            services::core_repositories(config.setup_database.unwrap_or_default());
 
-        if config.setup_database.unwrap_or_default() {
-            repos_to_clone.push(Repository::new("lichess-org", "lila-db-seed"));
-        }
+        repos_to_clone.extend(
+            services
+                .iter()
+                .flat_map(|service| service.repositories.iter().cloned()),
+        );
 
-        let optional_repos: Vec<Repository> = services
+        let repos_to_clone: Vec<Repository> = repos_to_clone
             .iter()
-            .filter_map(|service| service.repositories.clone())
-            .flatten()
+            .map(|repo| config.repository(repo))
             .collect();
+        clone_repositories(&repos_to_clone, config.docker_runtime, clone_jobs, answers)?;
+    }
 
-        repos_to_clone.extend(optional_repos);
+    outro("Starting services...")?;
+    Ok(())
+}
 
-        for repo in repos_to_clone {
-            let progress = spinner();
-            progress.start(format!("Cloning {}...", repo.full_name()));
+/// What to do with a repository directory that already has something in it
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum CheckoutAction {
+    /// Fix the remote and finish the checkout, keeping what is there
+    Repair,
+    /// Delete the directory and clone it again
+    Reclone,
+    /// Use the directory as it is
+    Adopt,
+}
 
-            if repo.clone_path().read_dir()?.next().is_some() {
-                progress.stop(format!("✓ Already cloned {}", repo.full_name()));
-                continue;
+enum ClonePlan {
+    Clone,
+    Repair,
+    Reclone,
+    Keep(&'static str),
+}
+
+/// Clone every repository that is not cloned yet, `jobs` at a time, after asking
+/// what to do with any directory that holds a broken or unexpected checkout.
+/// A failed clone does not stop the others, and all failures are reported together at the end.
+fn clone_repositories(
+    repos: &[Repository],
+    runtime: Option<docker::Runtime>,
+    jobs: NonZeroUsize,
+    answers: Option<&Answers>,
+) -> Result {
+    let mut plans = vec![];
+    for repo in repos {
+        let checkout = repo.inspect()?;
+        let plan = match (&checkout, checkout.problem()) {
+            (Checkout::Missing, _) => ClonePlan::Clone,
+            (_, None) => ClonePlan::Keep("Already cloned"),
+            (_, Some(problem)) => {
+                let path = repo.clone_path();
+                warning(format!("{} {problem}", path.display()))?;
+                match prompt_for_checkout_action(repo, &checkout, answers)? {
+                    CheckoutAction::Repair => ClonePlan::Repair,
+                    CheckoutAction::Reclone => ClonePlan::Reclone,
+                    CheckoutAction::Adopt => ClonePlan::Keep("Using existing"),
+                }
             }
+        };
+        plans.push(plan);
+    }
+    warn_about_ownership(runtime, repos)?;
 
-            let mut cmd = Command::new("git");
-            cmd.env("GIT_LFS_SKIP_SMUDGE", "1")
-                .arg("clone")
-                .arg("--origin")
-                .arg("upstream")
-                .arg("--depth")
-                .arg("1")
-                .arg("--recurse-submodules")
-                .arg(repo.url())
-                .arg(repo.clone_path());
-
-            let output = cmd.output()?;
-            assert!(
-                output.status.success(),
-                "Failed to clone repo: {} - {output:?}",
-                repo.full_name()
-            );
+    let multi = multi_progress(format!("Cloning {} repositories...", repos.len()));
+    let jobs_with_bars: Vec<_> = repos
+        .iter()
+        .zip(&plans)
+        .map(|(repo, plan)| {
+            let bar = multi.add(spinner());
+            bar.start(format!("Waiting to clone {}", repo.full_name()));
+            (repo, plan, bar)
+        })
+        .collect();
+
+    let results = repository::parallel(&jobs_with_bars, jobs, |(repo, plan, bar)| {
+        let (verb, done, result) = match plan {
+            ClonePlan::Keep(how) => {
+                bar.stop(format!("✓ {how} {}", repo.full_name()));
+                return Ok(());
+            }
+            ClonePlan::Clone => {
+                bar.start(format!("Cloning {}...", repo.full_name()));
+                ("clone", "Cloned", repo.git_clone())
+            }
+            ClonePlan::Repair => {
+                bar.start(format!("Repairing {}...", repo.full_name()));
+                ("repair", "Repaired", repo.repair())
+            }
+            ClonePlan::Reclone => {
+                bar.start(format!("Re-cloning {}...", repo.full_name()));
+                ("re-clone", "Re-cloned", repo.reclone())
+            }
+        };
+        match result {
+            Ok(()) => bar.stop(format!("✓ {done} {}", repo.full_name())),
+            Err(_) => bar.error(format!("✗ Failed to {verb} {}", repo.full_name())),
+        }
+        result
+    });
+
+    let failures: Vec<String> = repos
+        .iter()
+        .zip(results)
+        .filter_map(|(repo, result)| {
+            result
+                .err()
+                .map(|err: Error| format!("{}: {err}", repo.full_name()))
+        })
+        .collect();
+    if failures.is_empty() {
+        multi.stop();
+        return Ok(());
+    }
+
+    multi.error(format!(
+        "Failed to clone {} of {} repositories",
+        failures.len(),
+        repos.len()
+    ));
+    Err(Error::Git(format!(
+        "Failed to clone:\n{}\nFix the problem and re-run `./lila-docker add-services` or `./lila-docker setup`; repositories that were cloned are kept.",
+        failures.join("\n")
+    )))
+}
 
-            progress.stop(format!("✓ Cloned {}", repo.full_name()));
+fn prompt_for_checkout_action(
+    repo: &Repository,
+    checkout: &Checkout,
+    answers: Option<&Answers>,
+) -> Result<CheckoutAction> {
+    let path = repo.clone_path();
+
+    if let Some(answers) = answers {
+        let action = Answers::require("broken_checkouts", answers.broken_checkouts.as_ref())?;
+        if action == CheckoutAction::Repair && !checkout.is_repairable() {
+            return Err(Answers::invalid(
+                "broken_checkouts",
+                &format!(
+                    "{} is not a git repository and cannot be repaired, use `reclone` or `adopt`",
+                    path.display()
+                ),
+            ));
         }
+        return Ok(action);
+    }
+
+    let mut prompt = select(format!("What should be done with {}?", path.display()));
+    if checkout.is_repairable() {
+        prompt = prompt.item(
+            CheckoutAction::Repair,
+            "Repair",
+            format!(
+                "Point a remote at {} and finish the checkout, keeping local changes",
+                repo.url()
+            ),
+        );
+    }
+    prompt
+        .item(
+            CheckoutAction::Reclone,
+            "Re-clone",
+            format!("Delete {} and clone it again", path.display()),
+        )
+        .item(
+            CheckoutAction::Adopt,
+            "Use as is",
+            "Leave the directory untouched",
+        )
+        .interact()
+        .map_err(Error::prompt)
+}
+
+/// Warn about repository directories that the containers may not be able to write to,
+/// because they are not owned by the user the containers write as on `runtime`.
+fn warn_about_ownership(runtime: Option<docker::Runtime>, repos: &[Repository]) -> Result {
+    let Some((user_id, group_id)) = docker::files_owner(runtime, docker::env_user()) else {
+        return Ok(());
+    };
+    let mismatches: Vec<String> = repos
+        .iter()
+        .map(Repository::clone_path)
+        .filter_map(|path| {
+            repository::owner_mismatch(&path, user_id, group_id).map(|(uid, gid)| {
+                format!(
+                    "{} is owned by {uid}:{gid}, fix with `sudo chown -R {user_id}:{group_id} {}`",
+                    path.display(),
+                    path.display()
+                )
+            })
+        })
+        .collect();
+    if !mismatches.is_empty() {
+        warning(format!(
+            "The containers write as {user_id}:{group_id} and may not be able to write to:\n{}",
+            mismatches.join("\n")
+        ))?;
+    }
+    Ok(())
+}
+
+/// Say which of the services in `profiles` need files kept in Git LFS, and confirm before
+/// continuing without it.
+fn check_git_lfs(profiles: &[String], answers: Option<&Answers>) -> Result {
+    let needed = services::lfs_content(profiles);
+    if repository::has_git_lfs() {
+        success("✓ Git LFS is installed")?;
+        return Ok(());
+    }
+    if needed.is_empty() {
+        info("Git LFS is not installed, but none of the selected services need it")?;
+        return Ok(());
+    }
+
+    let proceed = if let Some(answers) = answers {
+        Answers::require(
+            "continue_without_git_lfs",
+            answers.continue_without_git_lfs.as_ref(),
+        )?
+    } else {
+        confirm(
+            [
+                "Git LFS is not installed. Without it these files will be missing:".to_string(),
+                needed
+                    .iter()
+                    .map(|(service, content)| format!("  {service}: {content}"))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                "You can install it later from https://git-lfs.com/ and run `./lila-docker lfs --fetch`.".to_string(),
+                "Do you want to continue anyway?".to_string(),
+            ]
+            .join("\n"),
+        )
+        .initial_value(true)
+        .interact()
+        .map_err(Error::prompt)?
+    };
+    if proceed {
+        Ok(())
+    } else {
+        Err(Error::Cancelled)
     }
+}
 
-    outro("Starting services...")
+fn check_resource_budget(services: &[OptionalService], answers: Option<&Answers>) -> Result {
+    let required = Resources::BASE
+        + services
+            .iter()
+            .map(|service| service.resources)
+            .sum::<Resources>();
+    let shortfalls = HostLimits::detect().shortfalls(required);
+    if shortfalls.is_empty() {
+        return Ok(());
+    }
+
+    note(
+        "Estimated resource usage:",
+        format!("{required}\n\n{}", shortfalls.join("\n")),
+    )?;
+
+    let proceed = if let Some(answers) = answers {
+        Answers::require(
+            "continue_over_budget",
+            answers.continue_over_budget.as_ref(),
+        )?
+    } else {
+        confirm(
+            "The selected services may need more resources than this machine has. Continue anyway?",
+        )
+        .initial_value(false)
+        .interact()
+        .map_err(Error::prompt)?
+    };
+    if proceed {
+        Ok(())
+    } else {
+        Err(Error::Cancelled)
+    }
 }
 
-fn create_placeholder_dirs() {
+fn create_placeholder_dirs() -> Result {
     // Create a placeholder directory for each of the repos
     // otherwise the directories will be created by Docker
     // when the volumes are mounted and they may be owned by root
-    [
-        Repository::new("lichess-org", "lila"),
-        Repository::new("lichess-org", "lila-ws"),
-        Repository::new("lichess-org", "lila-db-seed"),
-        Repository::new("lichess-org", "lila-fishnet"),
-        Repository::new("lichess-org", "lila-engine"),
-        Repository::new("lichess-org", "lila-gif"),
-        Repository::new("lichess-org", "lila-push"),
-        Repository::new("lichess-org", "api"),
-        Repository::new("lichess-org", "chessground"),
-        Repository::new("lichess-org", "pgn-viewer"),
-        Repository::new("lichess-org", "scalachess"),
-        Repository::new("lichess-org", "berserk"),
-        Repository::new("cyanfish", "bbpPairings"),
-    ]
-    .iter()
-    .map(Repository::clone_path)
-    .for_each(|path| {
-        std::fs::create_dir_all(path).unwrap();
-    });
+    for path in services::all_repositories()
+        .iter()
+        .map(Repository::clone_path)
+    {
+        std::fs::create_dir_all(path)?;
+    }
+    Ok(())
 }
 
-#[allow(clippy::too_many_lines)]
-fn prompt_for_services() -> Result<Vec<OptionalService<'static>>, Error> {
+fn prompt_for_services(answers: Option<&Answers>) -> Result<Vec<OptionalService>> {
+    let services = services::catalog();
+
+    if let Some(answers) = answers {
+        return Answers::require("services", answers.services.as_ref())?
+            .iter()
+            .map(|id| {
+                services
+                    .iter()
+                    .find(|service| service.id == id)
+                    .cloned()
+                    .ok_or_else(|| {
+                        Answers::invalid(
+                            "services",
+                            &format!(
+                                "unknown service `{id}`, expected one of: {}",
+                                services
+                                    .iter()
+                                    .map(|service| service.id)
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                            ),
+                        )
+                    })
+            })
+            .collect();
+    }
+
     multiselect(
         "Select which optional services to include:\n⚠️  Only enable the services you need. Enabling multiple services can consume a lot of resources and may cause performance issues.\n(Use arrows, <space> to toggle, <enter> to continue)\n",
     )
     .required(false)
-    .item(
-        OptionalService {
-            compose_profile: vec!["mongo-express"].into(),
-            repositories: None,
-        },
-        "Database admin interface",
-        "Mongo Express for viewing database structure and data",
-    )
-    .item(
-        OptionalService {
-            compose_profile: vec!["lila-ws-build"].into(),
-            repositories: vec![Repository::new("lichess-org", "lila-ws")].into(),
-        },
-        "Websocket source code",
-        "Only needed if you want to make changes, otherwise a prebuilt lila-ws image will be used",
-    )
-    .item(
-        OptionalService {
-            compose_profile: vec!["email"].into(),
-            repositories: None,
-        },
-        "Outbound email testing",
-        "for capturing and debugging outbound email",
-    )
-    .item(
-        OptionalService {
-            compose_profile: vec!["stockfish-play"].into(),
-            repositories: vec![Repository::new("lichess-org", "lila-fishnet")].into(),
-        },
-        "Stockfish Play",
-        "for playing against the computer",
-    )
-    .item(
-        OptionalService {
-            compose_profile: vec!["stockfish-analysis"].into(),
-            repositories: None,
-        },
-        "Stockfish Game Analysis",
-        "for requesting computer analysis of games",
-    )
-    .item(
-        OptionalService {
-            compose_profile: vec!["external-engine"].into(),
-            repositories: vec![Repository::new("lichess-org", "lila-engine")].into(),
-        },
-        "External Engine",
-        "for connecting a local chess engine to the analysis board",
-    )
-    .item(
-        OptionalService {
-            compose_profile: vec!["search"].into(),
-            repositories: None,
-        },
-        "Search",
-        "for searching games, forum posts, etc",
-    )
-    .item(
-        OptionalService {
-            compose_profile: vec!["gifs"].into(),
-            repositories: vec![Repository::new("lichess-org", "lila-gif")].into(),
-        },
-        "GIF + image generation",
-        "for generating animated GIFs and screenshots of games",
-    )
-    .item(
-        OptionalService {
-            compose_profile: vec!["push"].into(),
-            repositories: vec![Repository::new("lichess-org", "lila-push")].into(),
-        },
-        "Push server",
-        "for Lichess notifications",
-    )
-    .item(
-        OptionalService {
-            compose_profile: vec!["thumbnails"].into(),
-            repositories: None,
-        },
-        "Image uploads + thumbnails",
-        "for blog/coach/streamer images",
-    )
-    .item(
-        OptionalService {
-            compose_profile: vec!["api-docs"].into(),
-            repositories: vec![Repository::new("lichess-org", "api")].into(),
-        },
-        "API docs",
-        "standalone API documentation",
-    )
-    .item(
-        OptionalService {
-            compose_profile: vec!["chessground"].into(),
-            repositories: vec![Repository::new("lichess-org", "chessground")].into(),
-        },
-        "Chessground",
-        "standalone board UI",
-    )
-    .item(
-        OptionalService {
-            compose_profile: vec!["pgn-viewer"].into(),
-            repositories: vec![Repository::new("lichess-org", "pgn-viewer")].into(),
-        },
-        "PGN Viewer",
-        "standalone PGN viewer",
-    )
-    .item(
-        OptionalService {
-            compose_profile: None ,
-            repositories: vec![Repository::new("lichess-org", "scalachess")].into(),
-        },
-        "Scalachess",
-        "standalone chess logic library",
-    )
-    .item(
-        OptionalService {
-            compose_profile: None,
-            repositories: vec![Repository::new("lichess-org", "berserk")].into(),
-        },
-        "Berserk",
-        "Python API client",
-    )
-    .item(
-        OptionalService {
-            compose_profile: vec!["monitoring"].into(),
-            repositories: None,
-        },
-        "Monitoring",
-        "Metric collection using InfluxDB",
-    )
-    .item(
-        OptionalService {
-            compose_profile: vec!["swiss-pairings"].into(),
-            repositories: vec![Repository::new("cyanfish", "bbpPairings")].into(),
-        },
-        "Swiss Pairings",
-        "bbpPairings tool",
+    .items(
+        &services
+            .iter()
+            .map(|service| (service.clone(), service.label, service.hint))
+            .collect::<Vec<_>>(),
     )
     .interact()
+    .map_err(Error::prompt)
 }
 
-fn prompt_for_options(first_setup: bool) -> Result<Vec<Setting>, Error> {
+fn prompt_for_options(first_setup: bool, answers: Option<&Answers>) -> Result<Vec<Setting>> {
+    if let Some(answers) = answers {
+        return Answers::require("options", answers.options.as_ref());
+    }
+
     multiselect("Select options:\n")
         .required(false)
         .item(
@@ -579,9 +1326,10 @@ fn prompt_for_options(first_setup: bool) -> Result<Vec<Setting>, Error> {
             vec![Setting::EnableRateLimiting]
         })
         .interact()
+        .map_err(Error::prompt)
 }
 
-fn hostname(mut config: Config) -> std::io::Result<()> {
+fn hostname(mut config: Config) -> Result {
     let local_ip = match local_ip() {
         Ok(ip) => ip.to_string(),
         _ => "127.0.0.1".to_string(),
@@ -601,29 +1349,372 @@ fn hostname(mut config: Config) -> std::io::Result<()> {
             "For accessing from an Android emulator running on this machine",
         )
         .item("other", "Other", "Enter a custom hostname")
-        .interact()?
+        .interact()
+        .map_err(Error::prompt)?
     {
-        "other" => input("Enter a custom hostname:  (It must be resolvable)").interact()?,
+        "other" => input("Enter a custom hostname:  (It must be resolvable)")
+            .interact()
+            .map_err(Error::prompt)?,
         selection => selection.to_string(),
     };
 
-    config.lila_domain = Some(format!("{hostname}:8080"));
-    config.lila_url = Some(format!("http://{hostname}:8080"));
+    config.set_hostname(&hostname);
+    config.save()?;
+
+    outro(format!(
+        "✔ Local Lichess URL set to {}",
+        config.lila_url.unwrap_or_default()
+    ))?;
+    Ok(())
+}
+
+fn detect_runtime(mut config: Config) -> Result {
+    let Some(info) = docker::info() else {
+        // Docker may only be starting up, so go on as the shell script before this did
+        warning(format!(
+            "Could not reach Docker, assuming {}. Run `./lila-docker doctor` if the containers cannot write to ./repos",
+            docker::Runtime::Engine.label()
+        ))?;
+        return write_container_user(docker::Runtime::Engine);
+    };
+    write_container_user(info.runtime)?;
+    success(format!("✓ Running on {}", info.runtime.label()))?;
+
+    if config.docker_runtime != Some(info.runtime) {
+        config.save_docker_runtime(info.runtime)?;
+    }
+    Ok(())
+}
+
+/// Write the user the containers run as on `runtime` to `.env`
+fn write_container_user(runtime: docker::Runtime) -> Result {
+    let user = runtime.container_user().ok_or_else(|| {
+        Error::Docker(format!(
+            "Could not tell which user the containers should run as on {}",
+            runtime.label()
+        ))
+    })?;
+    docker::write_env_user(user)
+}
+
+fn update_devcontainer() -> Result {
+    if devcontainer::write()? {
+        success(format!("✓ Updated {}", devcontainer::PATH))?;
+    } else {
+        info(format!("{} is up to date", devcontainer::PATH))?;
+    }
+    Ok(())
+}
+
+fn doctor(config: Result<Config>, json: bool) -> Result {
+    let facts = if json {
+        doctor::Facts::gather(config)
+    } else {
+        intro("lila-docker doctor")?;
+        let progress = spinner();
+        progress.start("Checking the host...");
+        let facts = doctor::Facts::gather(config);
+        progress.stop("✓ Checked the host");
+        facts
+    };
+    let reports = doctor::run(&facts);
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&reports).map_err(Error::json_output)?
+        );
+    } else {
+        for report in &reports {
+            let message = match &report.fix {
+                Some(fix) => format!("{}: {}\n  ↳ {fix}", report.name, report.message),
+                None => format!("{}: {}", report.name, report.message),
+            };
+            match report.outcome {
+                doctor::Outcome::Pass => success(message)?,
+                doctor::Outcome::Warn => warning(message)?,
+                doctor::Outcome::Fail => cliclack::log::error(message)?,
+            }
+        }
+    }
+
+    let count = |outcome| {
+        reports
+            .iter()
+            .filter(|report| report.outcome == outcome)
+            .count()
+    };
+    let (failed, warned) = (count(doctor::Outcome::Fail), count(doctor::Outcome::Warn));
+    if failed > 0 {
+        return Err(Error::Host(format!(
+            "{failed} of {} checks failed",
+            reports.len()
+        )));
+    }
+    if !json {
+        outro(if warned == 0 {
+            "No problems found".to_string()
+        } else {
+            format!("{warned} warnings, see the suggested fixes above")
+        })?;
+    }
+    Ok(())
+}
+
+/// Check that the host ports of the enabled services are free, and offer to publish
+/// the taken ones on other ports. Returns the services that were moved.
+fn resolve_port_conflicts(
+    config: &mut Config,
+    noninteractive: bool,
+    answers: Option<&Answers>,
+) -> Result<Vec<String>> {
+    let published = compose::published_ports(config)?;
+    let running = ports::running_services(config);
+    let conflicts = ports::conflicts(&published, &running, ports::is_free);
+    if conflicts.is_empty() {
+        return Ok(vec![]);
+    }
+
+    warning(format!(
+        "These host ports are already in use:\n{}",
+        conflicts
+            .iter()
+            .map(|port| format!(
+                "{} ({}, port {} in the container)",
+                port.host, port.service, port.container
+            ))
+            .collect::<Vec<_>>()
+            .join("\n")
+    ))?;
+    let remap = if noninteractive {
+        true
+    } else if let Some(answers) = answers {
+        Answers::require("remap_ports", answers.remap_ports.as_ref())?
+    } else {
+        confirm("Publish them on other host ports?")
+            .initial_value(true)
+            .interact()
+            .map_err(Error::prompt)?
+    };
+    if !remap {
+        warning("The services using them will fail to start until the ports are free")?;
+        return Ok(vec![]);
+    }
+
+    // Also keep clear of the ports of services that could be added later
+    let mut reserved: Vec<u16> = published
+        .iter()
+        .map(|port| port.host)
+        .chain(
+            services::catalog()
+                .iter()
+                .flat_map(|service| service.ports.iter().map(|port| port.number)),
+        )
+        .collect();
+    let mut remapped = vec![];
+    for conflict in conflicts {
+        let suggestion = ports::suggest(conflict.host, &reserved, ports::is_free);
+        let host = if noninteractive || answers.is_some() {
+            suggestion.ok_or_else(|| {
+                Error::Config(format!(
+                    "No free host port found for {} near {}",
+                    conflict.service, conflict.host
+                ))
+            })?
+        } else {
+            let taken = reserved.clone();
+            let mut prompt = input(format!(
+                "Host port for {} (port {} in the container)",
+                conflict.service, conflict.container
+            ))
+            .validate(move |value: &String| match value.parse::<u16>() {
+                Ok(port) if port > 0 && !taken.contains(&port) && ports::is_free(port) => Ok(()),
+                Ok(_) => Err("That port is in use"),
+                Err(_) => Err("Enter a port number"),
+            });
+            if let Some(suggestion) = suggestion {
+                prompt = prompt
+                    .placeholder(&suggestion.to_string())
+                    .default_input(&suggestion.to_string());
+            }
+            prompt.interact().map_err(Error::prompt)?
+        };
+
+        reserved.push(host);
+        config
+            .services
+            .get_or_insert_with(Default::default)
+            .entry(conflict.service.clone())
+            .or_default()
+            .ports
+            .get_or_insert_with(Default::default)
+            .insert(conflict.container.clone(), host);
+        if ["caddy", "mono"].contains(&conflict.service.as_str()) {
+            config.remap_lila_port(conflict.host, host);
+        }
+        success(format!(
+            "✓ {} now publishes port {} on host port {host}",
+            conflict.service, conflict.container
+        ))?;
+        remapped.push(conflict.service.clone());
+    }
+    Ok(remapped)
+}
+
+fn check_ports(mut config: Config, noninteractive: bool, recreate: bool) -> Result {
+    let lila_port = config.lila_port();
+    let remapped = resolve_port_conflicts(&mut config, noninteractive, None)?;
+    if remapped.is_empty() {
+        return Ok(());
+    }
     config.save()?;
 
-    outro(format!("✔ Local Lichess URL set to http://{hostname}:8080"))
+    // Every service reads lila's URL from the environment, so moving it affects them all
+    let services = if config.lila_port() == lila_port {
+        remapped
+    } else {
+        vec![]
+    };
+    if recreate {
+        ports::recreate(&config, &services)
+    } else {
+        info("Run `docker compose up -d` to publish the new ports")?;
+        Ok(())
+    }
+}
+
+fn list_urls(config: &Config, json: bool) -> Result {
+    let urls = urls::directory(config, &compose::published_ports(config)?);
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&urls).map_err(Error::json_output)?
+        );
+    } else {
+        note("URLs", urls::table(&urls))?;
+    }
+    Ok(())
+}
+
+/// How long one poll of an endpoint may take
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long to wait between polls of the endpoints that are not up yet
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How many log lines to show for each service that never came up
+const FAILURE_LOG_LINES: usize = 20;
+
+/// Poll lila and the HTTP endpoints of the enabled services until they all answer,
+/// showing the logs of those that still do not after `timeout`
+fn wait_until_ready(config: &Config, timeout: Duration) -> Result {
+    let endpoints = health::endpoints(config, &compose::published_ports(config)?);
+    let multi = multi_progress(format!(
+        "Waiting up to {} minutes for the services to respond...",
+        timeout.as_secs() / 60
+    ));
+    let bars: Vec<_> = endpoints
+        .iter()
+        .map(|endpoint| {
+            let bar = multi.add(spinner());
+            bar.start(format!("{}: starting ({})", endpoint.label, endpoint.url));
+            bar
+        })
+        .collect();
+
+    let deadline = Instant::now() + timeout;
+    let mut pending: Vec<usize> = (0..endpoints.len()).collect();
+    loop {
+        pending.retain(|&i| {
+            let endpoint = &endpoints[i];
+            match health::probe(&endpoint.url, PROBE_TIMEOUT) {
+                Ok(status) if health::is_ready(status) => {
+                    bars[i].stop(format!("✓ {} is up at {}", endpoint.label, endpoint.url));
+                    return false;
+                }
+                Ok(status) => bars[i].set_message(format!(
+                    "{}: answered {status}, still starting ({})",
+                    endpoint.label, endpoint.url
+                )),
+                Err(err) => bars[i].set_message(format!(
+                    "{}: {err}, still starting ({})",
+                    endpoint.label, endpoint.url
+                )),
+            }
+            true
+        });
+        if pending.is_empty() || Instant::now() >= deadline {
+            break;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    if pending.is_empty() {
+        multi.stop();
+        return Ok(());
+    }
+    for &i in &pending {
+        bars[i].error(format!(
+            "✗ {} did not respond at {}",
+            endpoints[i].label, endpoints[i].url
+        ));
+    }
+    multi.error(format!(
+        "{} of {} services are not up after {} minutes",
+        pending.len(),
+        endpoints.len(),
+        timeout.as_secs() / 60
+    ));
+
+    // A service with several endpoints is reported once
+    let mut services: Vec<&str> = pending
+        .iter()
+        .map(|&i| endpoints[i].service.as_str())
+        .collect();
+    services.sort_unstable();
+    services.dedup();
+    for service in &services {
+        let logs = health::last_logs(config, service, FAILURE_LOG_LINES);
+        note(
+            format!("Last log lines of {service}:"),
+            if logs.is_empty() {
+                "(no logs; is the container running?)".to_string()
+            } else {
+                logs
+            },
+        )?;
+    }
+    Err(Error::Docker(format!(
+        "Not responding: {}. See `./lila-docker logs` for more.",
+        services.join(", ")
+    )))
 }
 
-fn welcome(config: Config) -> std::io::Result<()> {
+fn welcome(config: Config, wait: Option<NonZeroU64>) -> Result {
     intro("Your Lichess instance is starting!")?;
 
+    if let Some(minutes) = wait {
+        wait_until_ready(&config, Duration::from_secs(minutes.get() * 60))?;
+    }
+
+    let urls = urls::directory(&config, &compose::published_ports(&config)?);
     note(
-        "The site will be available at:",
-        config
-            .lila_url
-            .unwrap_or("http://localhost:8080".to_owned()),
+        "The site and services will be available at:",
+        urls::table(&urls),
     )?;
 
+    let enabled = services::enabled_services(&config.compose_profiles.unwrap_or_default());
+    if !enabled.is_empty() {
+        note(
+            "Optional services:",
+            enabled
+                .iter()
+                .map(|service| format!("{} ({})", service.label, service.hint))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )?;
+    }
+
     note(
         "For full documentation, see:",
         "https://github.com/lichess-org/lila-docker",
@@ -631,104 +1722,52 @@ fn welcome(config: Config) -> std::io::Result<()> {
 
     note("To monitor the progress:", "./lila-docker logs")?;
 
-    outro("🚀")
-}
-
-fn has_git_lfs() -> bool {
-    Command::new("git")
-        .arg("lfs")
-        .arg("version")
-        .output()
-        .is_ok_and(|output| output.status.success())
+    outro("🚀")?;
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use clap::CommandFactory;
 
     #[test]
-    fn test_repository() {
-        let repo = Repository::new("lichess-org", "lila");
-        assert_eq!(repo.org, "lichess-org");
-        assert_eq!(repo.project, "lila");
-        assert_eq!(repo.full_name(), "lichess-org/lila");
-        assert_eq!(repo.url(), "https://github.com/lichess-org/lila");
-        assert_eq!(repo.clone_path(), Path::new("repos/lila"));
+    fn test_cli() {
+        Cli::command().debug_assert();
     }
 
     #[test]
-    fn test_to_env_proc() {
-        let foo = Some("test");
-        assert_eq!(to_env!(foo), "FOO=test");
+    fn test_cli_add_services_alias() {
+        let cli = Cli::try_parse_from(["command", "add_services"]).unwrap();
+        assert!(matches!(cli.command, Commands::AddServices { .. }));
     }
 
     #[test]
-    fn test_set_env_vars_from_struct() {
-        let contents = Config {
-            quick_setup: Some(true),
-            compose_profiles: Some(vec!["foo".to_string(), "bar".to_string()]),
-            lila_ws_container: Some("image".to_string()),
-            setup_database: Some(true),
-            setup_bbppairings: Some(false),
-            mock_email: Some(false),
-            enable_monitoring: Some(false),
-            enable_rate_limiting: Some(true),
-            su_password: Some("foo".to_string()),
-            password: Some("bar".to_string()),
-            setup_api_tokens: Some(false),
-            lila_domain: Some("baz:8080".to_string()),
-            lila_url: Some("http://baz:8080".to_string()),
-        }
-        .to_env();
-
-        assert_eq!(
-            contents,
-            vec![
-                "QUICK_SETUP=true",
-                "COMPOSE_PROFILES=foo,bar",
-                "LILA_WS_CONTAINER=image",
-                "SETUP_DATABASE=true",
-                "SETUP_BBPPAIRINGS=false",
-                "MOCK_EMAIL=false",
-                "ENABLE_MONITORING=false",
-                "ENABLE_RATE_LIMITING=true",
-                "SU_PASSWORD=foo",
-                "PASSWORD=bar",
-                "SETUP_API_TOKENS=false",
-                "LILA_DOMAIN=baz:8080",
-                "LILA_URL=http://baz:8080",
-            ]
-            .join("\n")
-        );
+    fn test_cli_unknown_command() {
+        assert!(Cli::try_parse_from(["command", "foo"]).is_err());
+        assert!(Cli::try_parse_from(["command"]).is_err());
     }
 
     #[test]
-    fn test_env_removes_empty_lines() {
-        let contents = Config {
-            quick_setup: None,
-            compose_profiles: None,
-            lila_ws_container: None,
-            setup_database: None,
-            setup_bbppairings: None,
-            mock_email: None,
-            enable_monitoring: None,
-            enable_rate_limiting: None,
-            su_password: None,
-            password: None,
-            setup_api_tokens: None,
-            lila_domain: Some("baz:8080".to_string()),
-            lila_url: Some("http://baz:8080".to_string()),
-        }
-        .to_env();
-
+    fn test_services_from_answers() {
+        let answers = Answers {
+            services: Some(vec!["email".to_string(), "berserk".to_string()]),
+            ..Default::default()
+        };
+        let services = prompt_for_services(Some(&answers)).unwrap();
         assert_eq!(
-            contents,
-            vec![
-                "COMPOSE_PROFILES=",
-                "LILA_DOMAIN=baz:8080",
-                "LILA_URL=http://baz:8080"
-            ]
-            .join("\n")
+            services.iter().map(|s| s.id).collect::<Vec<_>>(),
+            ["email", "berserk"]
         );
+
+        let answers = Answers {
+            services: Some(vec!["emial".to_string()]),
+            ..Default::default()
+        };
+        let err = prompt_for_services(Some(&answers)).unwrap_err();
+        assert!(err.to_string().contains("unknown service `emial`"));
+
+        let err = prompt_for_services(Some(&Answers::default())).unwrap_err();
+        assert!(err.to_string().contains("`services`"));
     }
 }
@@ -0,0 +1,128 @@
+use std::{
+    net::{Ipv4Addr, TcpListener},
+    process::Command,
+};
+
+use crate::{
+    compose::PublishedPort,
+    error::{Error, Result},
+    Config,
+};
+
+/// How far above a taken port to look for a free one
+const SEARCH_RANGE: u16 = 100;
+
+/// Whether nothing on the host is listening on `port`
+pub fn is_free(port: u16) -> bool {
+    TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)).is_ok()
+}
+
+/// The published ports that cannot be bound: taken by another program, or published
+/// twice. Ports of `running` services are theirs already and are skipped.
+pub fn conflicts<'a>(
+    published: &'a [PublishedPort],
+    running: &[String],
+    is_free: impl Fn(u16) -> bool,
+) -> Vec<&'a PublishedPort> {
+    published
+        .iter()
+        .enumerate()
+        .filter(|(i, port)| {
+            let duplicate = published[..*i].iter().any(|other| other.host == port.host);
+            duplicate || (!running.contains(&port.service) && !is_free(port.host))
+        })
+        .map(|(_, port)| port)
+        .collect()
+}
+
+/// The first free port above `port` that is not `reserved` for another service
+pub fn suggest(port: u16, reserved: &[u16], is_free: impl Fn(u16) -> bool) -> Option<u16> {
+    (1..=SEARCH_RANGE)
+        .filter_map(|offset| port.checked_add(offset))
+        .find(|candidate| !reserved.contains(candidate) && is_free(*candidate))
+}
+
+/// The compose services that are running now, none if Docker cannot tell
+pub fn running_services(config: &Config) -> Vec<String> {
+    Command::new("docker")
+        .args(["compose", "ps", "--services", "--status", "running"])
+        .envs(config.env())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(ToString::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Recreate `services`, or every enabled service when it is empty, so they publish their new ports
+pub fn recreate(config: &Config, services: &[String]) -> Result {
+    let status = Command::new("docker")
+        .args(["compose", "up", "--detach", "--no-deps"])
+        .args(services)
+        .envs(config.env())
+        .status()
+        .map_err(|err| Error::Docker(format!("Could not run docker: {err}")))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::Docker(format!(
+            "`docker compose up` failed ({status})"
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn port(service: &str, host: u16) -> PublishedPort {
+        PublishedPort {
+            service: service.to_string(),
+            container: "80".to_string(),
+            host,
+            default_host: host,
+        }
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let published = [
+            port("caddy", 8080),
+            port("mailpit", 8025),
+            port("picfit", 8025),
+        ];
+        let is_free = |port| port != 8025;
+
+        assert_eq!(
+            conflicts(&published, &[], is_free),
+            [&published[1], &published[2]]
+        );
+        assert_eq!(
+            conflicts(&published, &["mailpit".to_string()], is_free),
+            [&published[2]]
+        );
+        assert!(conflicts(&published[..2], &[], |_| true).is_empty());
+    }
+
+    #[test]
+    fn test_suggest() {
+        assert_eq!(suggest(8080, &[], |_| true), Some(8081));
+        assert_eq!(suggest(8080, &[8081], |port| port != 8082), Some(8083));
+        assert_eq!(suggest(u16::MAX, &[], |_| true), None);
+        assert_eq!(suggest(8080, &[], |_| false), None);
+    }
+
+    #[test]
+    fn test_is_free() {
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(!is_free(port));
+        drop(listener);
+        assert!(is_free(port));
+    }
+}
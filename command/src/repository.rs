@@ -0,0 +1,1344 @@
+use serde::Serialize;
+use std::{
+    fmt::Write as _,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use crate::error::{Error, Result};
+
+/// Branches that `repos pull` keeps up to date, in order of preference
+const MAIN_BRANCHES: [&str; 2] = ["main", "master"];
+
+/// Remotes that `repos pull` pulls from, in order of preference
+const REMOTES: [&str; 2] = ["upstream", "origin"];
+
+/// The clone depth used unless `settings.toml` says otherwise
+pub const DEFAULT_DEPTH: u32 = 1;
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Repository {
+    pub org: String,
+    pub project: String,
+    /// URL of the contributor's fork, added as the `origin` remote
+    pub fork: Option<String>,
+    /// Branch to check out instead of the upstream default branch.
+    /// Fetched from the fork when there is one.
+    pub branch: Option<String>,
+    /// `--depth` of the clone, `None` for the full history
+    pub depth: Option<u32>,
+    /// A worktree of the clone that is mounted into the containers instead of the clone itself
+    pub worktree: Option<PathBuf>,
+}
+
+impl Repository {
+    pub fn new(org: &str, project: &str) -> Self {
+        Self {
+            org: org.to_string(),
+            project: project.to_string(),
+            fork: None,
+            branch: None,
+            depth: Some(DEFAULT_DEPTH),
+            worktree: None,
+        }
+    }
+
+    pub fn full_name(&self) -> String {
+        format!("{}/{}", self.org, self.project)
+    }
+
+    pub fn url(&self) -> String {
+        format!("https://github.com/{}/{}", self.org, self.project)
+    }
+
+    pub fn clone_path(&self) -> PathBuf {
+        Path::new("repos").join(&self.project)
+    }
+
+    /// The checkout the containers use: the active worktree, or else the clone
+    pub fn active_path(&self) -> PathBuf {
+        self.worktree.clone().unwrap_or_else(|| self.clone_path())
+    }
+
+    /// The remotes of a clone as (name, URL) pairs: `upstream`, then `origin` if there is a fork
+    pub fn remotes(&self) -> Vec<(&'static str, String)> {
+        let mut remotes = vec![("upstream", self.url())];
+        if let Some(fork) = &self.fork {
+            remotes.push(("origin", fork.clone()));
+        }
+        remotes
+    }
+
+    /// What is at `clone_path()`: nothing yet, a usable clone, or something to repair or replace.
+    pub fn inspect(&self) -> Result<Checkout> {
+        inspect(&self.clone_path(), &self.url())
+    }
+
+    /// Make a `Checkout::Incomplete` or `Checkout::WrongRemote` clone usable again,
+    /// keeping any commits and changes it already has.
+    pub fn repair(&self) -> Result {
+        repair(&self.clone_path(), &self.url(), self.depth)
+    }
+
+    /// Fetch pull request `number` from the upstream remote into a `pr-<number>` branch
+    /// and switch the active checkout to it. An existing branch is only fast-forwarded.
+    pub fn checkout_pull_request(&self, number: u32) -> Result<PullRequest> {
+        checkout_pull_request(&self.active_path(), &self.url(), number)
+    }
+
+    /// Delete whatever is at `clone_path()` and clone it from scratch.
+    pub fn reclone(&self) -> Result {
+        let path = self.clone_path();
+        std::fs::remove_dir_all(&path)?;
+        std::fs::create_dir_all(&path)?;
+        self.git_clone()
+    }
+
+    /// Clone into `clone_path()` without smudging LFS files,
+    /// then add the fork and check out the configured branch.
+    pub fn git_clone(&self) -> Result<()> {
+        self.clone_to(&self.remotes(), &self.clone_path())
+    }
+
+    /// `remotes` are (name, URL) pairs. The first is cloned from, and `branch`
+    /// is checked out from the last.
+    fn clone_to(&self, remotes: &[(&str, String)], path: &Path) -> Result<()> {
+        let [(first_name, first_url), others @ ..] = remotes else {
+            return Err(Error::Git("no remote to clone from".to_string()));
+        };
+        let depth = self.depth.map(|depth| format!("--depth={depth}"));
+
+        let mut args = vec!["clone", "--origin", first_name, "--recurse-submodules"];
+        args.extend(depth.as_deref());
+        if let (true, Some(branch)) = (others.is_empty(), &self.branch) {
+            args.extend(["--branch", branch]);
+        }
+        let path_arg = path.to_string_lossy();
+        args.extend([first_url.as_str(), &path_arg]);
+        git_with_env(Path::new("."), &args, &[("GIT_LFS_SKIP_SMUDGE", "1")])?;
+
+        for (name, url) in others {
+            git(path, &["remote", "add", name, url])?;
+        }
+
+        if let (Some((name, _)), Some(branch)) = (others.last(), &self.branch) {
+            let mut fetch = vec!["fetch", "--quiet"];
+            fetch.extend(depth.as_deref());
+            fetch.extend([name, branch.as_str()]);
+            git(path, &fetch)?;
+            git(
+                path,
+                &["switch", "--quiet", "--track", &format!("{name}/{branch}")],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// What `Repository::inspect` found at a clone path
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum Checkout {
+    /// Does not exist or is empty, ready to be cloned into
+    Missing,
+    /// A git repository with the expected remote and a commit checked out
+    Complete,
+    /// Has files in it, but is not a git repository
+    NotGit,
+    /// A git repository whose clone or checkout did not finish, and why
+    Incomplete(String),
+    /// A complete git repository with no remote pointing at the expected URL.
+    /// Holds its remotes as `name → url`.
+    WrongRemote(Vec<String>),
+}
+
+impl Checkout {
+    /// Why the checkout cannot be used as is, `None` when it can be cloned into or used
+    pub fn problem(&self) -> Option<String> {
+        match self {
+            Self::Missing | Self::Complete => None,
+            Self::NotGit => Some("is not a git repository".to_string()),
+            Self::Incomplete(reason) => Some(reason.clone()),
+            Self::WrongRemote(remotes) if remotes.is_empty() => Some("has no remotes".to_string()),
+            Self::WrongRemote(remotes) => Some(format!(
+                "has no remote for the expected repository ({})",
+                remotes.join(", ")
+            )),
+        }
+    }
+
+    /// Whether `Repository::repair` can fix it
+    pub fn is_repairable(&self) -> bool {
+        matches!(self, Self::Incomplete(_) | Self::WrongRemote(_))
+    }
+}
+
+fn inspect(path: &Path, url: &str) -> Result<Checkout> {
+    let Ok(mut entries) = path.read_dir() else {
+        return Ok(Checkout::Missing);
+    };
+    if entries.next().is_none() {
+        return Ok(Checkout::Missing);
+    }
+    if !path.join(".git").exists() {
+        return Ok(Checkout::NotGit);
+    }
+    if git(path, &["rev-parse", "--verify", "--quiet", "HEAD"]).is_err() {
+        return Ok(Checkout::Incomplete(
+            "has no commit checked out, the clone may have been interrupted".to_string(),
+        ));
+    }
+    if !git(path, &["rev-parse", "--git-path", "index"])
+        .is_ok_and(|index| path.join(index).exists())
+    {
+        return Ok(Checkout::Incomplete(
+            "has no files checked out, the checkout may have been interrupted".to_string(),
+        ));
+    }
+
+    let remotes = remotes(path)?;
+    if remotes
+        .iter()
+        .any(|(_, remote_url)| same_url(remote_url, url))
+    {
+        Ok(Checkout::Complete)
+    } else {
+        Ok(Checkout::WrongRemote(
+            remotes
+                .into_iter()
+                .map(|(name, url)| format!("{name} → {url}"))
+                .collect(),
+        ))
+    }
+}
+
+/// The (name, URL) of every remote of the repository at `path`
+fn remotes(path: &Path) -> Result<Vec<(String, String)>> {
+    git(path, &["remote"])?
+        .lines()
+        .map(|name| Ok((name.to_string(), git(path, &["remote", "get-url", name])?)))
+        .collect()
+}
+
+/// Whether two remote URLs point at the same repository, ignoring
+/// SSH vs HTTPS, a trailing `.git` or `/`, and case.
+fn same_url(a: &str, b: &str) -> bool {
+    let normalize = |url: &str| {
+        let url = url.trim().trim_end_matches('/');
+        let url = url.strip_suffix(".git").unwrap_or(url);
+        url.replacen("git@github.com:", "https://github.com/", 1)
+            .replacen("ssh://git@github.com/", "https://github.com/", 1)
+            .to_lowercase()
+    };
+    normalize(a) == normalize(b)
+}
+
+fn repair(path: &Path, url: &str, depth: Option<u32>) -> Result {
+    let remotes = remotes(path)?;
+    let remote = match remotes
+        .iter()
+        .find(|(_, remote_url)| same_url(remote_url, url))
+    {
+        Some((name, _)) => name.clone(),
+        None if remotes.iter().any(|(name, _)| name == "upstream") => {
+            git(path, &["remote", "set-url", "upstream", url])?;
+            "upstream".to_string()
+        }
+        None => {
+            git(path, &["remote", "add", "upstream", url])?;
+            "upstream".to_string()
+        }
+    };
+    let no_smudge = [("GIT_LFS_SKIP_SMUDGE", "1")];
+
+    if git(path, &["rev-parse", "--verify", "--quiet", "HEAD"]).is_err() {
+        // Nothing was checked out, so start again from the remote's default branch
+        let depth = depth.map(|depth| format!("--depth={depth}"));
+        let mut fetch = vec!["fetch", "--quiet"];
+        fetch.extend(depth.as_deref());
+        fetch.push(&remote);
+        git(path, &fetch)?;
+        git(path, &["remote", "set-head", &remote, "--auto"])?;
+        let head = git(
+            path,
+            &[
+                "symbolic-ref",
+                "--short",
+                &format!("refs/remotes/{remote}/HEAD"),
+            ],
+        )?;
+        let branch = head.strip_prefix(&format!("{remote}/")).unwrap_or(&head);
+        git_with_env(
+            path,
+            &[
+                "checkout", "--quiet", "--force", "-B", branch, "--track", &head,
+            ],
+            &no_smudge,
+        )?;
+    } else if !git(path, &["rev-parse", "--git-path", "index"])
+        .is_ok_and(|index| path.join(index).exists())
+    {
+        // Without an index there are no changes to lose
+        git_with_env(path, &["reset", "--quiet", "--hard", "HEAD"], &no_smudge)?;
+    }
+    Ok(())
+}
+
+/// The (uid, gid) owning `path`, when it differs from `user_id`/`group_id`.
+/// Docker Desktop (`USER_ID=0`) maps ownership itself, so nothing is reported there.
+#[cfg(unix)]
+pub fn owner_mismatch(path: &Path, user_id: u32, group_id: u32) -> Option<(u32, u32)> {
+    use std::os::unix::fs::MetadataExt;
+
+    if user_id == 0 {
+        return None;
+    }
+    let metadata = path.metadata().ok()?;
+    let owner = (metadata.uid(), metadata.gid());
+    (owner != (user_id, group_id)).then_some(owner)
+}
+
+#[cfg(not(unix))]
+pub fn owner_mismatch(_path: &Path, _user_id: u32, _group_id: u32) -> Option<(u32, u32)> {
+    None
+}
+
+/// One `git worktree` of a clone, including the clone itself
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Worktree {
+    pub path: PathBuf,
+    /// The checked out branch, `None` when detached
+    pub branch: Option<String>,
+}
+
+/// Fail unless `path` is a clone, as git would otherwise use the lila-docker repository around it
+fn require_clone(path: &Path) -> Result {
+    if is_clone(path) {
+        Ok(())
+    } else {
+        Err(Error::Git(format!("{} is not cloned", path.display())))
+    }
+}
+
+/// Every worktree of the clone at `path`, the clone itself first
+pub fn worktrees(path: &Path) -> Result<Vec<Worktree>> {
+    require_clone(path)?;
+    Ok(parse_worktrees(&git(
+        path,
+        &["worktree", "list", "--porcelain"],
+    )?))
+}
+
+/// Parse the output of `git worktree list --porcelain`
+fn parse_worktrees(porcelain: &str) -> Vec<Worktree> {
+    porcelain
+        .split("\n\n")
+        .filter_map(|block| {
+            let mut lines = block.lines();
+            let path = lines.next()?.strip_prefix("worktree ")?;
+            let branch = lines
+                .find_map(|line| line.strip_prefix("branch refs/heads/"))
+                .map(ToString::to_string);
+            Some(Worktree {
+                path: PathBuf::from(path),
+                branch,
+            })
+        })
+        .collect()
+}
+
+/// Add a worktree of the clone at `clone` in `path` with `branch` checked out.
+/// The branch is created from the current `HEAD` unless it exists locally or on a remote.
+pub fn add_worktree(clone: &Path, path: &Path, branch: &str) -> Result {
+    require_clone(clone)?;
+    let exists = git(
+        clone,
+        &[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("refs/heads/{branch}"),
+        ],
+    )
+    .is_ok()
+        || !git(
+            clone,
+            &["for-each-ref", &format!("refs/remotes/*/{branch}")],
+        )?
+        .is_empty();
+
+    let path = std::path::absolute(path)?;
+    let path_arg = path.to_string_lossy();
+    let mut args = vec!["worktree", "add", "--quiet"];
+    if exists {
+        args.extend([path_arg.as_ref(), branch]);
+    } else {
+        args.extend(["-b", branch, path_arg.as_ref()]);
+    }
+    git_with_env(clone, &args, &[("GIT_LFS_SKIP_SMUDGE", "1")])?;
+    Ok(())
+}
+
+/// A pull request checked out by `Repository::checkout_pull_request`
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct PullRequest {
+    pub branch: String,
+    /// Files that differ between the previous `HEAD` and the pull request
+    pub changed: Vec<String>,
+}
+
+fn checkout_pull_request(path: &Path, url: &str, number: u32) -> Result<PullRequest> {
+    if !git(path, &["status", "--porcelain", "--untracked-files=no"])?.is_empty() {
+        return Err(Error::Git(format!(
+            "{} has uncommitted changes, commit or stash them first",
+            path.display()
+        )));
+    }
+    let remote = remotes(path)?
+        .into_iter()
+        .find(|(_, remote_url)| same_url(remote_url, url))
+        .map(|(name, _)| name)
+        .ok_or_else(|| Error::Git(format!("{} has no remote for {url}", path.display())))?;
+
+    let previous = git(path, &["rev-parse", "HEAD"])?;
+    git(
+        path,
+        &[
+            "fetch",
+            "--quiet",
+            &remote,
+            &format!("refs/pull/{number}/head"),
+        ],
+    )?;
+    let branch = format!("pr-{number}");
+    let exists = git(
+        path,
+        &[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("refs/heads/{branch}"),
+        ],
+    )
+    .is_ok();
+    if !exists {
+        git(
+            path,
+            &["switch", "--quiet", "--create", &branch, "FETCH_HEAD"],
+        )?;
+    } else if git(
+        path,
+        &["rev-list", "--count", &format!("FETCH_HEAD..{branch}")],
+    )? == "0"
+    {
+        // the pull request only gained commits since the last review
+        git(path, &["switch", "--quiet", &branch])?;
+        git(path, &["merge", "--quiet", "--ff-only", "FETCH_HEAD"])?;
+    } else {
+        return Err(Error::Git(format!(
+            "{branch} in {} has commits that are not in pull request #{number}, because you committed to it or the pull request was rebased. \
+             Keep them with `git -C {} branch -m {branch} <new-name>`, or drop them with `git -C {} branch -D {branch}`, then review again",
+            path.display(),
+            path.display(),
+            path.display()
+        )));
+    }
+
+    let changed = git(path, &["diff", "--name-only", &previous, "HEAD"])?
+        .lines()
+        .map(ToString::to_string)
+        .collect();
+    Ok(PullRequest { branch, changed })
+}
+
+/// Run `f` on every item on at most `jobs` threads at once, returning the results in order.
+pub fn parallel<T: Sync, R: Send>(
+    items: &[T],
+    jobs: NonZeroUsize,
+    f: impl Fn(&T) -> R + Sync,
+) -> Vec<R> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..items.len()).map(|_| None).collect::<Vec<_>>());
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.get().min(items.len()) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(index) else {
+                    break;
+                };
+                let result = f(item);
+                results.lock().expect("results lock poisoned")[index] = Some(result);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .expect("results lock poisoned")
+        .into_iter()
+        .map(|result| result.expect("every item is processed"))
+        .collect()
+}
+
+/// Run `git` in `dir` and return its trimmed stdout, or its stderr as the error.
+pub fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    git_with_env(dir, args, &[])
+}
+
+fn git_with_env(dir: &Path, args: &[&str], env: &[(&str, &str)]) -> Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .envs(env.iter().copied())
+        .output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(Error::Git(format!(
+            "git {}: {}",
+            args.join(" "),
+            stderr.trim()
+        )))
+    }
+}
+
+/// What `repos status` or `repos pull` found (or did) in one checkout
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    UpToDate,
+    Ahead,
+    Behind,
+    Diverged,
+    Pulled,
+    /// Left alone, see `Status::message` for why
+    Skipped,
+    Failed,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize)]
+pub struct Status {
+    pub name: String,
+    pub path: PathBuf,
+    /// The checked out branch, `None` when detached or not a git repo
+    pub branch: Option<String>,
+    /// The remote branch compared against, e.g. `upstream/main`
+    pub tracking: Option<String>,
+    pub ahead: Option<u32>,
+    pub behind: Option<u32>,
+    pub dirty: bool,
+    pub outcome: Outcome,
+    pub message: Option<String>,
+}
+
+impl Status {
+    fn new(name: &str, path: &Path) -> Self {
+        Self {
+            name: name.to_string(),
+            path: path.to_path_buf(),
+            branch: None,
+            tracking: None,
+            ahead: None,
+            behind: None,
+            dirty: false,
+            outcome: Outcome::Skipped,
+            message: None,
+        }
+    }
+
+    fn skipped(mut self, message: impl Into<String>) -> Self {
+        self.outcome = Outcome::Skipped;
+        self.message = Some(message.into());
+        self
+    }
+
+    fn failed(mut self, err: &Error) -> Self {
+        self.outcome = Outcome::Failed;
+        self.message = Some(err.to_string());
+        self
+    }
+
+    fn summary(&self) -> String {
+        let message = self.message.as_deref().unwrap_or_default();
+        match self.outcome {
+            Outcome::UpToDate => "🟢 up to date".to_string(),
+            Outcome::Ahead => "🔵 ahead".to_string(),
+            Outcome::Behind => format!("🟡 behind by {} commits", self.behind.unwrap_or(0)),
+            Outcome::Diverged => "🟠 diverged".to_string(),
+            Outcome::Pulled => "✅ pulled".to_string(),
+            Outcome::Skipped => format!("🟣 {message}"),
+            Outcome::Failed => format!("❌ {message}"),
+        }
+    }
+}
+
+/// lila-docker itself followed by every directory in `./repos`, as (name, path) pairs
+pub fn checkouts(root: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let mut repos = vec![];
+    let repos_dir = root.join("repos");
+    if repos_dir.is_dir() {
+        for entry in repos_dir.read_dir()? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                repos.push((
+                    entry.file_name().to_string_lossy().to_string(),
+                    entry.path(),
+                ));
+            }
+        }
+    }
+    repos.sort();
+    repos.insert(0, ("lila-docker".to_string(), root.to_path_buf()));
+    Ok(repos)
+}
+
+/// Whether `path` is a clone of its own, rather than an empty placeholder inside lila-docker
+pub fn is_clone(path: &Path) -> bool {
+    path.join(".git").exists()
+}
+
+/// The directories in `./repos` that have been cloned, as (name, path) pairs
+pub fn clones(root: &Path) -> Result<Vec<(String, PathBuf)>> {
+    Ok(checkouts(root)?
+        .into_iter()
+        .skip(1)
+        .filter(|(_, path)| is_clone(path))
+        .collect())
+}
+
+/// Fetch the main branch of every checkout in parallel and compare it with `HEAD`.
+/// When `pull` is set, fast-forward clean checkouts that are on their main branch.
+/// A failure in one checkout is reported in its `Status` and does not affect the others.
+pub fn statuses(checkouts: &[(String, PathBuf)], pull: bool) -> Vec<Status> {
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = checkouts
+            .iter()
+            .map(|(name, path)| scope.spawn(move || status(name, path, pull)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("status thread panicked"))
+            .collect()
+    })
+}
+
+fn status(name: &str, path: &Path, pull: bool) -> Status {
+    let status = Status::new(name, path);
+    if !path.join(".git").exists() {
+        return status.skipped("not a git repo");
+    }
+    match check(status.clone(), pull) {
+        Ok(status) => status,
+        Err(err) => status.failed(&err),
+    }
+}
+
+fn check(mut status: Status, pull: bool) -> Result<Status> {
+    let path = status.path.clone();
+
+    status.branch = Some(git(&path, &["branch", "--show-current"])?).filter(|b| !b.is_empty());
+    status.dirty = !git(&path, &["status", "--porcelain", "--untracked-files=no"])?.is_empty();
+
+    let Some(main_branch) = MAIN_BRANCHES.into_iter().find(|branch| {
+        git(
+            &path,
+            &[
+                "rev-parse",
+                "--verify",
+                "--quiet",
+                &format!("refs/heads/{branch}"),
+            ],
+        )
+        .is_ok()
+    }) else {
+        return Ok(status.skipped("no main or master branch"));
+    };
+
+    let remotes = git(&path, &["remote"])?;
+    let Some(remote) = REMOTES
+        .into_iter()
+        .find(|remote| remotes.lines().any(|line| line == *remote))
+    else {
+        return Ok(status.skipped("no upstream or origin remote"));
+    };
+
+    git(&path, &["fetch", "--quiet", remote, main_branch])?;
+
+    let tracking = format!("{remote}/{main_branch}");
+    let counts = git(
+        &path,
+        &[
+            "rev-list",
+            "--left-right",
+            "--count",
+            &format!("HEAD...{tracking}"),
+        ],
+    )?;
+    let (ahead, behind) = parse_counts(&counts)
+        .ok_or_else(|| Error::Git(format!("unexpected `git rev-list` output: {counts}")))?;
+    status.tracking = Some(tracking.clone());
+    status.ahead = Some(ahead);
+    status.behind = Some(behind);
+
+    if status.branch.as_deref() != Some(main_branch) {
+        let current = status.branch.clone();
+        let current = current.as_deref().unwrap_or("detached HEAD");
+        return Ok(status.skipped(format!("not on {main_branch} branch ({current})")));
+    }
+
+    status.outcome = match (ahead, behind) {
+        (0, 0) => Outcome::UpToDate,
+        (_, 0) => Outcome::Ahead,
+        (0, _) => Outcome::Behind,
+        _ => Outcome::Diverged,
+    };
+
+    if pull && status.outcome == Outcome::Behind {
+        if status.dirty {
+            return Ok(status.skipped("has uncommitted changes"));
+        }
+        git(&path, &["merge", "--ff-only", "--quiet", &tracking])?;
+        status.behind = Some(0);
+        status.outcome = Outcome::Pulled;
+    }
+
+    Ok(status)
+}
+
+/// How much history `repos deepen` fetches into a shallow clone
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum History {
+    Full,
+    /// The last N commits
+    Depth(u32),
+    /// Every commit since a date git understands, e.g. `2024-01-01`
+    Since(String),
+}
+
+/// Fetch more history into the clone at `path` from its upstream (or origin) remote,
+/// returning a description of how much it has now.
+pub fn deepen(path: &Path, history: &History) -> Result<String> {
+    // git would otherwise deepen the lila-docker repository that contains `path`
+    if !is_clone(path) {
+        return Err(Error::Git(format!("{} is not cloned", path.display())));
+    }
+    let is_shallow =
+        || -> Result<bool> { Ok(git(path, &["rev-parse", "--is-shallow-repository"])? == "true") };
+    if !is_shallow()? {
+        return Ok("already has the full history".to_string());
+    }
+
+    let remotes = git(path, &["remote"])?;
+    let remote = REMOTES
+        .into_iter()
+        .find(|remote| remotes.lines().any(|line| line == *remote))
+        .ok_or_else(|| Error::Git("no upstream or origin remote".to_string()))?;
+    let arg = match history {
+        History::Full => "--unshallow".to_string(),
+        History::Depth(depth) => format!("--depth={depth}"),
+        History::Since(date) => format!("--shallow-since={date}"),
+    };
+    git(path, &["fetch", "--quiet", &arg, remote])?;
+
+    let commits = git(path, &["rev-list", "--count", "HEAD"])?;
+    Ok(if is_shallow()? {
+        format!("{commits} commits")
+    } else {
+        format!("full history, {commits} commits")
+    })
+}
+
+/// The first line of every Git LFS pointer file
+const LFS_POINTER: &str = "version https://git-lfs.github.com/spec/v1";
+
+/// Pointer files are a few lines of text, anything bigger is real content
+const LFS_POINTER_MAX_BYTES: u64 = 1024;
+
+pub fn has_git_lfs() -> bool {
+    git(Path::new("."), &["lfs", "version"]).is_ok()
+}
+
+/// Tracked files in the checkout at `path` and its submodules that are still
+/// LFS pointers, because the clone skipped downloading them.
+pub fn lfs_pointers(path: &Path) -> Result<Vec<PathBuf>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(path)
+        .args([
+            "grep",
+            "--recurse-submodules",
+            "--files-with-matches",
+            "-I",
+            "--fixed-strings",
+            LFS_POINTER,
+        ])
+        .output()?;
+    // `git grep` exits with 1 when nothing matches
+    match output.status.code() {
+        Some(0) => {}
+        Some(1) if output.stderr.is_empty() => return Ok(vec![]),
+        _ => {
+            return Err(Error::Git(format!(
+                "git grep: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )))
+        }
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(PathBuf::from)
+        .filter(|file| is_lfs_pointer(&path.join(file)))
+        .collect())
+}
+
+fn is_lfs_pointer(file: &Path) -> bool {
+    file.metadata()
+        .is_ok_and(|metadata| metadata.len() <= LFS_POINTER_MAX_BYTES)
+        && std::fs::read_to_string(file)
+            .is_ok_and(|contents| contents.lines().next() == Some(LFS_POINTER))
+}
+
+/// Download and check out the LFS files of the checkout at `path` and its submodules.
+pub fn lfs_pull(path: &Path) -> Result {
+    if !has_git_lfs() {
+        return Err(Error::Git(
+            "Git LFS is not installed, see https://git-lfs.com/".to_string(),
+        ));
+    }
+    git(path, &["lfs", "pull"])?;
+    git(
+        path,
+        &[
+            "submodule",
+            "foreach",
+            "--quiet",
+            "--recursive",
+            "git lfs pull",
+        ],
+    )?;
+    Ok(())
+}
+
+/// Parse the `<ahead>\t<behind>` output of `git rev-list --left-right --count`
+fn parse_counts(counts: &str) -> Option<(u32, u32)> {
+    let (ahead, behind) = counts.split_once(char::is_whitespace)?;
+    Some((ahead.trim().parse().ok()?, behind.trim().parse().ok()?))
+}
+
+pub fn table(statuses: &[Status]) -> String {
+    let headers = ["REPOSITORY", "BRANCH", "AHEAD", "BEHIND", "DIRTY", "STATUS"];
+    let count = |n: Option<u32>| n.map_or_else(|| "-".to_string(), |n| n.to_string());
+    let rows: Vec<[String; 6]> = statuses
+        .iter()
+        .map(|status| {
+            [
+                status.name.clone(),
+                status.branch.clone().unwrap_or_else(|| "-".to_string()),
+                count(status.ahead),
+                count(status.behind),
+                if status.dirty { "yes" } else { "no" }.to_string(),
+                status.summary(),
+            ]
+        })
+        .collect();
+
+    let mut widths = headers.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut table = String::new();
+    for row in std::iter::once(headers.map(str::to_string)).chain(rows) {
+        let mut line = String::new();
+        for (width, cell) in widths.iter().zip(&row) {
+            let _ = write!(line, "{cell:<width$}  ");
+        }
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repository() {
+        let repo = Repository::new("lichess-org", "lila");
+        assert_eq!(repo.org, "lichess-org");
+        assert_eq!(repo.project, "lila");
+        assert_eq!(repo.full_name(), "lichess-org/lila");
+        assert_eq!(repo.url(), "https://github.com/lichess-org/lila");
+        assert_eq!(repo.clone_path(), Path::new("repos/lila"));
+    }
+
+    #[test]
+    fn test_parallel() {
+        let running = AtomicUsize::new(0);
+        let most = AtomicUsize::new(0);
+        let items: Vec<u64> = (0..10).collect();
+        let results = parallel(&items, NonZeroUsize::new(3).unwrap(), |item| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            most.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(10 * (item % 3)));
+            running.fetch_sub(1, Ordering::SeqCst);
+            item * 2
+        });
+        assert_eq!(results, (0..10).map(|item| item * 2).collect::<Vec<_>>());
+        assert!(most.load(Ordering::SeqCst) <= 3);
+    }
+
+    #[test]
+    fn test_parse_counts() {
+        assert_eq!(parse_counts("0\t3"), Some((0, 3)));
+        assert_eq!(parse_counts("12\t0"), Some((12, 0)));
+        assert_eq!(parse_counts("x"), None);
+    }
+
+    #[test]
+    fn test_table() {
+        let mut behind = Status::new("lila", Path::new("repos/lila"));
+        behind.branch = Some("master".to_string());
+        behind.ahead = Some(0);
+        behind.behind = Some(4);
+        behind.outcome = Outcome::Behind;
+        let not_git =
+            Status::new("scalachess", Path::new("repos/scalachess")).skipped("not a git repo");
+
+        assert_eq!(
+            table(&[behind, not_git]),
+            "REPOSITORY  BRANCH  AHEAD  BEHIND  DIRTY  STATUS\n\
+             lila        master  0      4       no     🟡 behind by 4 commits\n\
+             scalachess  -       -      -       no     🟣 not a git repo\n"
+        );
+    }
+
+    /// A bare "upstream" repo with one commit and a clone of it, in a fresh temp directory
+    fn upstream_and_clone(name: &str) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("lila-docker-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let upstream = dir.join("upstream.git");
+        let clone = dir.join("clone");
+        git(
+            &dir,
+            &[
+                "init",
+                "--quiet",
+                "--bare",
+                "--initial-branch=main",
+                "upstream.git",
+            ],
+        )
+        .unwrap();
+        git(
+            &dir,
+            &[
+                "clone",
+                "--quiet",
+                "--origin",
+                "upstream",
+                "upstream.git",
+                "clone",
+            ],
+        )
+        .unwrap();
+        commit(&clone, "first");
+        git(&clone, &["push", "--quiet", "upstream", "main"]).unwrap();
+        (upstream, clone)
+    }
+
+    fn commit(dir: &Path, message: &str) {
+        git(
+            dir,
+            &[
+                "-c",
+                "user.name=test",
+                "-c",
+                "user.email=test@example.com",
+                "commit",
+                "--quiet",
+                "--allow-empty",
+                "-m",
+                message,
+            ],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_status_and_pull() {
+        let (upstream, clone) = upstream_and_clone("pull");
+        let dir = upstream.parent().unwrap();
+
+        // push a commit from a second clone so the first one falls behind
+        git(dir, &["clone", "--quiet", "upstream.git", "other"]).unwrap();
+        commit(&dir.join("other"), "second");
+        git(&dir.join("other"), &["push", "--quiet", "origin", "main"]).unwrap();
+
+        let checkouts = [
+            ("clone".to_string(), clone.clone()),
+            ("missing".to_string(), dir.join("missing")),
+        ];
+        let [behind, missing] = statuses(&checkouts, false).try_into().unwrap();
+        assert_eq!(behind.outcome, Outcome::Behind);
+        assert_eq!(behind.tracking.as_deref(), Some("upstream/main"));
+        assert_eq!((behind.ahead, behind.behind), (Some(0), Some(1)));
+        assert_eq!(missing.outcome, Outcome::Skipped);
+
+        let [pulled, _] = statuses(&checkouts, true).try_into().unwrap();
+        assert_eq!(pulled.outcome, Outcome::Pulled);
+        assert_eq!(status("clone", &clone, false).outcome, Outcome::UpToDate);
+
+        git(&clone, &["switch", "--quiet", "-c", "feature"]).unwrap();
+        let feature = status("clone", &clone, true);
+        assert_eq!(feature.outcome, Outcome::Skipped);
+        assert_eq!(
+            feature.message.as_deref(),
+            Some("not on main branch (feature)")
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_remotes() {
+        let mut repo = Repository::new("lichess-org", "lila");
+        assert_eq!(
+            repo.remotes(),
+            [(
+                "upstream",
+                "https://github.com/lichess-org/lila".to_string()
+            )]
+        );
+
+        repo.fork = Some("git@github.com:me/lila.git".to_string());
+        assert_eq!(
+            repo.remotes()[1],
+            ("origin", "git@github.com:me/lila.git".to_string())
+        );
+    }
+
+    #[test]
+    fn test_clone_fork_branch() {
+        let (upstream, _) = upstream_and_clone("fork");
+        let dir = upstream.parent().unwrap();
+
+        // a fork with an extra branch
+        git(
+            dir,
+            &["clone", "--quiet", "--bare", "upstream.git", "fork.git"],
+        )
+        .unwrap();
+        git(dir, &["clone", "--quiet", "fork.git", "work"]).unwrap();
+        git(&dir.join("work"), &["switch", "--quiet", "-c", "feature"]).unwrap();
+        commit(&dir.join("work"), "on the fork");
+        git(&dir.join("work"), &["push", "--quiet", "origin", "feature"]).unwrap();
+
+        let repo = Repository {
+            fork: Some(dir.join("fork.git").to_string_lossy().to_string()),
+            branch: Some("feature".to_string()),
+            ..Repository::new("lichess-org", "lila")
+        };
+        let remotes = [
+            ("upstream", format!("file://{}", upstream.display())),
+            ("origin", repo.fork.clone().unwrap()),
+        ];
+        let path = dir.join("lila");
+        repo.clone_to(&remotes, &path).unwrap();
+
+        assert_eq!(git(&path, &["remote"]).unwrap(), "origin\nupstream");
+        assert_eq!(
+            git(&path, &["branch", "--show-current"]).unwrap(),
+            "feature"
+        );
+        assert_eq!(
+            git(&path, &["log", "-1", "--format=%s"]).unwrap(),
+            "on the fork"
+        );
+        assert_eq!(
+            git(&path, &["rev-parse", "--is-shallow-repository"]).unwrap(),
+            "true"
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_same_url() {
+        let url = "https://github.com/lichess-org/lila";
+        assert!(same_url(url, "https://github.com/lichess-org/lila.git"));
+        assert!(same_url(url, "git@github.com:lichess-org/lila.git"));
+        assert!(same_url(url, "https://github.com/Lichess-Org/lila/"));
+        assert!(!same_url(url, "https://github.com/me/lila"));
+    }
+
+    #[test]
+    fn test_inspect_and_repair() {
+        let (upstream, clone) = upstream_and_clone("inspect");
+        let dir = upstream.parent().unwrap();
+        let url = upstream.to_string_lossy().to_string();
+
+        assert_eq!(
+            inspect(&dir.join("missing"), &url).unwrap(),
+            Checkout::Missing
+        );
+        std::fs::create_dir(dir.join("empty")).unwrap();
+        assert_eq!(
+            inspect(&dir.join("empty"), &url).unwrap(),
+            Checkout::Missing
+        );
+        std::fs::write(dir.join("empty/file"), "").unwrap();
+        assert_eq!(inspect(&dir.join("empty"), &url).unwrap(), Checkout::NotGit);
+
+        assert_eq!(inspect(&clone, &url).unwrap(), Checkout::Complete);
+        let wrong = inspect(&clone, "https://github.com/lichess-org/lila").unwrap();
+        assert!(wrong.problem().unwrap().contains("upstream → "));
+
+        // a clone whose checkout was interrupted
+        std::fs::remove_file(clone.join(".git/index")).unwrap();
+        assert!(matches!(
+            inspect(&clone, &url).unwrap(),
+            Checkout::Incomplete(_)
+        ));
+        repair(&clone, &url, None).unwrap();
+        assert_eq!(inspect(&clone, &url).unwrap(), Checkout::Complete);
+
+        // a clone interrupted before anything was fetched, without a remote
+        let interrupted = dir.join("interrupted");
+        git(dir, &["init", "--quiet", "interrupted"]).unwrap();
+        assert!(inspect(&interrupted, &url).unwrap().is_repairable());
+        repair(&interrupted, &url, Some(1)).unwrap();
+        assert_eq!(inspect(&interrupted, &url).unwrap(), Checkout::Complete);
+        assert_eq!(
+            git(&interrupted, &["branch", "--show-current"]).unwrap(),
+            "main"
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_clones() {
+        let (upstream, clone) = upstream_and_clone("clones");
+        let root = upstream.parent().unwrap();
+        std::fs::create_dir_all(root.join("repos/placeholder")).unwrap();
+        git(
+            &root.join("repos"),
+            &["clone", "--quiet", &clone.to_string_lossy(), "lila"],
+        )
+        .unwrap();
+
+        assert_eq!(checkouts(root).unwrap().len(), 3);
+        assert_eq!(
+            clones(root).unwrap(),
+            [("lila".to_string(), root.join("repos/lila"))]
+        );
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_deepen() {
+        let (upstream, clone) = upstream_and_clone("deepen");
+        let dir = upstream.parent().unwrap();
+        for message in ["second", "third"] {
+            commit(&clone, message);
+        }
+        git(&clone, &["push", "--quiet", "upstream", "main"]).unwrap();
+
+        let shallow = dir.join("shallow");
+        Repository::new("lichess-org", "lila")
+            .clone_to(
+                &[("upstream", format!("file://{}", upstream.display()))],
+                &shallow,
+            )
+            .unwrap();
+        let commits = || git(&shallow, &["rev-list", "--count", "HEAD"]).unwrap();
+        assert_eq!(commits(), "1");
+
+        assert_eq!(deepen(&shallow, &History::Depth(2)).unwrap(), "2 commits");
+        assert_eq!(
+            deepen(&shallow, &History::Full).unwrap(),
+            "full history, 3 commits"
+        );
+        assert_eq!(
+            deepen(&shallow, &History::Full).unwrap(),
+            "already has the full history"
+        );
+
+        let placeholder = dir.join("placeholder");
+        std::fs::create_dir(&placeholder).unwrap();
+        assert!(matches!(
+            deepen(&placeholder, &History::Full),
+            Err(Error::Git(_))
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_lfs_pointers() {
+        let (upstream, clone) = upstream_and_clone("lfs");
+        assert_eq!(lfs_pointers(&clone).unwrap(), Vec::<PathBuf>::new());
+
+        std::fs::write(
+            clone.join("background.jpg"),
+            format!("{LFS_POINTER}\noid sha256:0123\nsize 12345\n"),
+        )
+        .unwrap();
+        std::fs::write(
+            clone.join("README.md"),
+            format!("Pointer files start with:\n{LFS_POINTER}\n"),
+        )
+        .unwrap();
+        git(&clone, &["add", "."]).unwrap();
+        assert_eq!(
+            lfs_pointers(&clone).unwrap(),
+            [PathBuf::from("background.jpg")]
+        );
+
+        std::fs::remove_dir_all(upstream.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_checkout_pull_request() {
+        let (upstream, clone) = upstream_and_clone("review");
+        let dir = upstream.parent().unwrap();
+        let url = upstream.to_string_lossy().to_string();
+
+        // GitHub exposes every pull request as `refs/pull/<number>/head`
+        git(dir, &["clone", "--quiet", "upstream.git", "contributor"]).unwrap();
+        let contributor = dir.join("contributor");
+        std::fs::create_dir(contributor.join("ui")).unwrap();
+        std::fs::write(contributor.join("ui/board.ts"), "").unwrap();
+        git(&contributor, &["add", "."]).unwrap();
+        commit(&contributor, "change the board");
+        git(
+            &contributor,
+            &["push", "--quiet", "origin", "HEAD:refs/pull/42/head"],
+        )
+        .unwrap();
+
+        let pull_request = checkout_pull_request(&clone, &url, 42).unwrap();
+        assert_eq!(
+            pull_request,
+            PullRequest {
+                branch: "pr-42".to_string(),
+                changed: vec!["ui/board.ts".to_string()],
+            }
+        );
+        assert_eq!(git(&clone, &["branch", "--show-current"]).unwrap(), "pr-42");
+        assert_eq!(
+            git(&clone, &["log", "-1", "--format=%s"]).unwrap(),
+            "change the board"
+        );
+
+        // checking it out again after the PR was updated moves the branch along
+        commit(&contributor, "address review");
+        git(
+            &contributor,
+            &[
+                "push",
+                "--quiet",
+                "--force",
+                "origin",
+                "HEAD:refs/pull/42/head",
+            ],
+        )
+        .unwrap();
+        let updated = checkout_pull_request(&clone, &url, 42).unwrap();
+        assert!(updated.changed.is_empty());
+        assert_eq!(
+            git(&clone, &["log", "-1", "--format=%s"]).unwrap(),
+            "address review"
+        );
+
+        // a local commit on the branch is never thrown away
+        commit(&clone, "try a fix");
+        assert!(matches!(
+            checkout_pull_request(&clone, &url, 42),
+            Err(Error::Git(_))
+        ));
+        assert_eq!(git(&clone, &["branch", "--show-current"]).unwrap(), "pr-42");
+        assert_eq!(
+            git(&clone, &["log", "-1", "--format=%s"]).unwrap(),
+            "try a fix"
+        );
+
+        assert!(checkout_pull_request(&clone, &url, 43).is_err());
+        assert!(checkout_pull_request(&clone, "https://github.com/lichess-org/lila", 42).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_worktrees() {
+        let (upstream, clone) = upstream_and_clone("worktree");
+        let dir = upstream.parent().unwrap();
+
+        add_worktree(&clone, &dir.join("feature"), "feature").unwrap();
+        add_worktree(&clone, &dir.join("main"), "main").unwrap_err();
+
+        let worktrees = worktrees(&clone).unwrap();
+        assert_eq!(
+            worktrees
+                .iter()
+                .map(|worktree| worktree.branch.as_deref())
+                .collect::<Vec<_>>(),
+            [Some("main"), Some("feature")]
+        );
+        assert!(worktrees[1].path.ends_with("feature"));
+
+        let placeholder = dir.join("placeholder");
+        std::fs::create_dir(&placeholder).unwrap();
+        assert!(matches!(
+            add_worktree(&placeholder, &dir.join("other"), "other"),
+            Err(Error::Git(_))
+        ));
+        assert!(matches!(super::worktrees(&placeholder), Err(Error::Git(_))));
+        assert!(!dir.join("other").exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_worktrees() {
+        let porcelain = "worktree /lila\nHEAD abc\nbranch refs/heads/master\n\nworktree /wt/review\nHEAD def\ndetached\n";
+        assert_eq!(
+            parse_worktrees(porcelain),
+            [
+                Worktree {
+                    path: PathBuf::from("/lila"),
+                    branch: Some("master".to_string()),
+                },
+                Worktree {
+                    path: PathBuf::from("/wt/review"),
+                    branch: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fetch_failure_is_reported() {
+        let (upstream, clone) = upstream_and_clone("fetch");
+        std::fs::remove_dir_all(&upstream).unwrap();
+
+        let failed = status("clone", &clone, false);
+        assert_eq!(failed.outcome, Outcome::Failed);
+        assert!(failed.message.unwrap().starts_with("git fetch"));
+
+        std::fs::remove_dir_all(upstream.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,242 @@
+use std::{fmt, iter::Sum, ops::Add, path::Path};
+
+/// Approximate memory, CPU and disk needed to run (and build) a set of containers.
+#[derive(Clone, Copy, Default, Eq, PartialEq, Debug)]
+pub struct Resources {
+    pub memory_mb: u64,
+    /// Thousandths of a CPU core, so fractional figures can be compared exactly
+    pub cpu_millis: u64,
+    pub disk_mb: u64,
+}
+
+impl Resources {
+    pub const fn new(memory_mb: u64, cpu_millis: u64, disk_mb: u64) -> Self {
+        Self {
+            memory_mb,
+            cpu_millis,
+            disk_mb,
+        }
+    }
+
+    /// lila, lila-ws, mongodb (primary + secondary), redis and caddy
+    pub const BASE: Self = Self::new(6144, 2000, 10_000);
+}
+
+impl Add for Resources {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self::new(
+            self.memory_mb + other.memory_mb,
+            self.cpu_millis + other.cpu_millis,
+            self.disk_mb + other.disk_mb,
+        )
+    }
+}
+
+impl Sum for Resources {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), Add::add)
+    }
+}
+
+impl fmt::Display for Resources {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} RAM, {} CPUs, {} disk",
+            format_mb(self.memory_mb),
+            format_cpus(self.cpu_millis),
+            format_mb(self.disk_mb)
+        )
+    }
+}
+
+/// What the host can offer. Any figure that could not be read is `None`
+/// and is not checked.
+#[derive(Clone, Copy, Default, Eq, PartialEq, Debug)]
+pub struct HostLimits {
+    pub memory_mb: Option<u64>,
+    pub cpu_millis: Option<u64>,
+    pub disk_mb: Option<u64>,
+}
+
+impl HostLimits {
+    /// The `hostRequirements` of `.devcontainer/devcontainer.json`
+    pub const DEVCONTAINER: Self = Self {
+        memory_mb: Some(8192),
+        cpu_millis: Some(4000),
+        disk_mb: None,
+    };
+
+    pub fn detect() -> Self {
+        let host = Self {
+            memory_mb: std::fs::read_to_string("/proc/meminfo")
+                .ok()
+                .and_then(|meminfo| parse_meminfo_total_mb(&meminfo)),
+            cpu_millis: std::thread::available_parallelism()
+                .ok()
+                .map(|cpus| cpus.get() as u64 * 1000),
+            disk_mb: free_disk_mb(Path::new(".")),
+        };
+
+        if is_devcontainer() {
+            host.min(Self::DEVCONTAINER)
+        } else {
+            host
+        }
+    }
+
+    fn min(self, other: Self) -> Self {
+        let min = |a: Option<u64>, b: Option<u64>| match (a, b) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        Self {
+            memory_mb: min(self.memory_mb, other.memory_mb),
+            cpu_millis: min(self.cpu_millis, other.cpu_millis),
+            disk_mb: min(self.disk_mb, other.disk_mb),
+        }
+    }
+
+    /// Describe every resource that `required` exceeds.
+    pub fn shortfalls(&self, required: Resources) -> Vec<String> {
+        let mut shortfalls = vec![];
+        if let Some(memory_mb) = self.memory_mb.filter(|&mb| required.memory_mb > mb) {
+            shortfalls.push(format!(
+                "Memory: needs ~{}, host has {}",
+                format_mb(required.memory_mb),
+                format_mb(memory_mb)
+            ));
+        }
+        if let Some(cpu_millis) = self
+            .cpu_millis
+            .filter(|&millis| required.cpu_millis > millis)
+        {
+            shortfalls.push(format!(
+                "CPU: needs ~{} cores, host has {}",
+                format_cpus(required.cpu_millis),
+                format_cpus(cpu_millis)
+            ));
+        }
+        if let Some(disk_mb) = self.disk_mb.filter(|&mb| required.disk_mb > mb) {
+            shortfalls.push(format!(
+                "Disk: needs ~{}, {} free",
+                format_mb(required.disk_mb),
+                format_mb(disk_mb)
+            ));
+        }
+        shortfalls
+    }
+}
+
+fn is_devcontainer() -> bool {
+    ["CODESPACES", "REMOTE_CONTAINERS"]
+        .iter()
+        .any(|var| std::env::var(var).is_ok_and(|value| value == "true"))
+}
+
+fn parse_meminfo_total_mb(meminfo: &str) -> Option<u64> {
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))
+        .and_then(|rest| rest.trim().strip_suffix("kB"))
+        .and_then(|kb| kb.trim().parse::<u64>().ok())
+        .map(|kb| kb / 1024)
+}
+
+#[cfg(unix)]
+fn free_disk_mb(path: &Path) -> Option<u64> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is a valid NUL-terminated string and `stat` is a valid out-pointer
+    if unsafe { libc::statvfs(path.as_ptr(), &raw mut stat) } != 0 {
+        return None;
+    }
+    #[allow(clippy::unnecessary_cast)] // the field widths differ between platforms
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64 / 1024 / 1024)
+}
+
+#[cfg(not(unix))]
+fn free_disk_mb(_path: &Path) -> Option<u64> {
+    None
+}
+
+fn format_mb(mb: u64) -> String {
+    if mb >= 1024 {
+        format!("{}.{} GB", mb / 1024, mb % 1024 * 10 / 1024)
+    } else {
+        format!("{mb} MB")
+    }
+}
+
+fn format_cpus(millis: u64) -> String {
+    if millis.is_multiple_of(1000) {
+        format!("{}", millis / 1000)
+    } else {
+        format!("{}.{}", millis / 1000, millis % 1000 / 100)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sum_and_display() {
+        let total: Resources = [
+            Resources::new(1536, 500, 200),
+            Resources::new(512, 1000, 800),
+        ]
+        .into_iter()
+        .sum();
+        assert_eq!(total, Resources::new(2048, 1500, 1000));
+        assert_eq!(total.to_string(), "2.0 GB RAM, 1.5 CPUs, 1000 MB disk");
+    }
+
+    #[test]
+    fn test_parse_meminfo() {
+        let meminfo = "MemTotal:       16318480 kB\nMemFree:         1234567 kB\n";
+        assert_eq!(parse_meminfo_total_mb(meminfo), Some(15936));
+        assert_eq!(parse_meminfo_total_mb("MemFree: 1 kB"), None);
+    }
+
+    #[test]
+    fn test_shortfalls() {
+        let host = HostLimits {
+            memory_mb: Some(8192),
+            cpu_millis: Some(4000),
+            disk_mb: None,
+        };
+
+        assert!(host
+            .shortfalls(Resources::new(8192, 4000, 999_999))
+            .is_empty());
+        assert_eq!(
+            host.shortfalls(Resources::new(10240, 4500, 0)),
+            [
+                "Memory: needs ~10.0 GB, host has 8.0 GB",
+                "CPU: needs ~4.5 cores, host has 4",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_devcontainer_caps_host() {
+        let host = HostLimits {
+            memory_mb: Some(65536),
+            cpu_millis: Some(2000),
+            disk_mb: Some(1000),
+        };
+        assert_eq!(
+            host.min(HostLimits::DEVCONTAINER),
+            HostLimits {
+                memory_mb: Some(8192),
+                cpu_millis: Some(2000),
+                disk_mb: Some(1000),
+            }
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use std::process::Command;
+
+use crate::{
+    error::{Error, Result},
+    Config,
+};
+
+/// A container that has to be rebuilt or restarted for checked out changes to take effect.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Rebuild {
+    /// Restart lila, which recompiles the Scala sources on startup
+    Lila,
+    /// Rebuild the frontend assets in `ui/`
+    Ui,
+    /// Restart lila-ws, which recompiles when built from source
+    LilaWs,
+}
+
+impl Rebuild {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Lila => "lila restart",
+            Self::Ui => "UI build",
+            Self::LilaWs => "lila-ws rebuild",
+        }
+    }
+
+    fn compose_args(self) -> &'static [&'static str] {
+        match self {
+            Self::Lila => &["restart", "lila"],
+            Self::Ui => &["run", "--rm", "ui", "/lila/ui/build", "--debug"],
+            Self::LilaWs => &["restart", "lila_ws"],
+        }
+    }
+
+    /// Run it with `docker compose`, using the profiles and settings of `config`.
+    pub fn run(self, config: &Config) -> Result {
+        let args = self.compose_args();
+        let status = Command::new("docker")
+            .arg("compose")
+            .args(args)
+            .envs(config.env())
+            .status()
+            .map_err(|err| Error::Docker(format!("Could not run docker: {err}")))?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(Error::Docker(format!(
+                "`docker compose {}` failed ({status})",
+                args.join(" ")
+            )))
+        }
+    }
+}
+
+/// What to rebuild after the `changed` files of `project` were checked out.
+/// Repositories that are not mounted into a running container need nothing.
+pub fn rebuilds(project: &str, changed: &[String], config: &Config) -> Vec<Rebuild> {
+    match project {
+        "lila" => {
+            let mut rebuilds = vec![];
+            if changed.iter().any(|file| !file.starts_with("ui/")) {
+                rebuilds.push(Rebuild::Lila);
+            }
+            if changed.iter().any(|file| file.starts_with("ui/")) {
+                rebuilds.push(Rebuild::Ui);
+            }
+            rebuilds
+        }
+        "lila-ws"
+            if config.lila_ws_container.as_deref() == Some("build") && !changed.is_empty() =>
+        {
+            vec![Rebuild::LilaWs]
+        }
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(files: &[&str]) -> Vec<String> {
+        files.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_rebuilds() {
+        let config = Config::default();
+        assert_eq!(
+            rebuilds(
+                "lila",
+                &files(&["modules/game/src/main/Game.scala"]),
+                &config
+            ),
+            [Rebuild::Lila]
+        );
+        assert_eq!(
+            rebuilds("lila", &files(&["ui/site/css/_board.scss"]), &config),
+            [Rebuild::Ui]
+        );
+        assert_eq!(
+            rebuilds(
+                "lila",
+                &files(&["ui/bits/src/bits.ts", "app/Env.scala"]),
+                &config
+            ),
+            [Rebuild::Lila, Rebuild::Ui]
+        );
+        assert!(rebuilds("lila", &[], &config).is_empty());
+
+        let changed = files(&["src/main/scala/Lila.scala"]);
+        assert!(rebuilds("lila-ws", &changed, &config).is_empty());
+        let config = Config {
+            lila_ws_container: Some("build".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(rebuilds("lila-ws", &changed, &config), [Rebuild::LilaWs]);
+        assert!(rebuilds("scalachess", &changed, &config).is_empty());
+    }
+}
@@ -0,0 +1,776 @@
+use crate::{resources::Resources, Config, Repository, Setting};
+
+/// A port published on the host by one of the service's containers.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Port {
+    pub number: u16,
+    pub label: &'static str,
+    /// The page to open in a browser
+    pub page: &'static str,
+    /// The path that answers with a success once the service is ready, if it serves HTTP
+    pub health: Option<&'static str>,
+    /// What to log in with, if the page asks
+    pub credentials: Option<&'static str>,
+}
+
+impl Port {
+    const fn new(number: u16, label: &'static str) -> Self {
+        Self {
+            number,
+            label,
+            page: "/",
+            health: None,
+            credentials: None,
+        }
+    }
+
+    const fn page(mut self, page: &'static str) -> Self {
+        self.page = page;
+        self
+    }
+
+    const fn health(mut self, path: &'static str) -> Self {
+        self.health = Some(path);
+        self
+    }
+
+    const fn credentials(mut self, credentials: &'static str) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+}
+
+/// A `Config` setting that is switched on by selecting a service.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Implies {
+    /// Compile bbpPairings and mount it into lila (`setup_bbppairings`)
+    BbpPairings,
+    /// Send email to Mailpit instead of mocking it (`mock_email = false`)
+    RealEmail,
+    /// Report metrics to the monitoring stack (`enable_monitoring`)
+    Monitoring,
+    /// Build lila-ws from source instead of using the image (`lila_ws_container`)
+    LilaWsBuild,
+}
+
+/// Something a service needs, or cannot run alongside.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum Target {
+    /// Another optional service, by id
+    Service(&'static str),
+    /// A compose profile that is not tied to an optional service (`base`, `quick`)
+    Profile(&'static str),
+    /// One of the setup options
+    Setting(Setting),
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Relation {
+    pub target: Target,
+    pub reason: &'static str,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct OptionalService {
+    pub id: &'static str,
+    pub label: &'static str,
+    pub hint: &'static str,
+    pub compose_profiles: Vec<&'static str>,
+    pub repositories: Vec<Repository>,
+    pub ports: Vec<Port>,
+    pub implies: Vec<Implies>,
+    pub requires: Vec<Relation>,
+    pub conflicts: Vec<Relation>,
+    /// Approximate footprint while running, including the first build
+    pub resources: Resources,
+}
+
+impl OptionalService {
+    fn new(id: &'static str, label: &'static str, hint: &'static str) -> Self {
+        Self {
+            id,
+            label,
+            hint,
+            compose_profiles: vec![],
+            repositories: vec![],
+            ports: vec![],
+            implies: vec![],
+            requires: vec![],
+            conflicts: vec![],
+            resources: Resources::default(),
+        }
+    }
+
+    fn profile(mut self, profile: &'static str) -> Self {
+        self.compose_profiles.push(profile);
+        self
+    }
+
+    fn repo(mut self, org: &str, project: &str) -> Self {
+        self.repositories.push(Repository::new(org, project));
+        self
+    }
+
+    fn port(mut self, port: Port) -> Self {
+        self.ports.push(port);
+        self
+    }
+
+    fn resources(mut self, memory_mb: u64, cpu_millis: u64, disk_mb: u64) -> Self {
+        self.resources = Resources::new(memory_mb, cpu_millis, disk_mb);
+        self
+    }
+
+    fn implies(mut self, implies: Implies) -> Self {
+        self.implies.push(implies);
+        self
+    }
+
+    fn requires(mut self, target: Target, reason: &'static str) -> Self {
+        self.requires.push(Relation { target, reason });
+        self
+    }
+
+    fn conflicts(mut self, target: Target, reason: &'static str) -> Self {
+        self.conflicts.push(Relation { target, reason });
+        self
+    }
+
+    pub fn has_implication(&self, implies: Implies) -> bool {
+        self.implies.contains(&implies)
+    }
+
+    /// Whether the service is part of a setup running `profiles`.
+    /// Services without a compose profile (plain repositories) never are.
+    pub fn is_enabled_by(&self, profiles: &[String]) -> bool {
+        !self.compose_profiles.is_empty()
+            && self
+                .compose_profiles
+                .iter()
+                .all(|profile| profiles.iter().any(|p| p == profile))
+    }
+}
+
+/// Every optional service that can be selected during setup.
+/// Adding a service to lila-docker only requires adding it here, then running
+/// `./lila-docker devcontainer` to label its ports in Codespaces.
+#[allow(clippy::too_many_lines)]
+pub fn catalog() -> Vec<OptionalService> {
+    vec![
+        OptionalService::new(
+            "mongo-express",
+            "Database admin interface",
+            "Mongo Express for viewing database structure and data",
+        )
+        .profile("mongo-express")
+        .port(Port::new(8081, "Mongo Express (Database explorer)").health("/"))
+        .resources(128, 100, 200),
+        OptionalService::new(
+            "lila-ws-build",
+            "Websocket source code",
+            "Only needed if you want to make changes, otherwise a prebuilt lila-ws image will be used",
+        )
+        .profile("lila-ws-build")
+        .repo("lichess-org", "lila-ws")
+        .implies(Implies::LilaWsBuild)
+        .conflicts(
+            Target::Profile("quick"),
+            "it replaces the lila-ws image, which the quick setup bundles inside the mono image",
+        )
+        .resources(2048, 1000, 2000),
+        OptionalService::new(
+            "email",
+            "Outbound email testing",
+            "for capturing and debugging outbound email",
+        )
+        .profile("email")
+        .port(Port::new(8025, "Mailpit (Outbound email debugging)").health("/readyz"))
+        .implies(Implies::RealEmail)
+        .resources(64, 100, 100),
+        OptionalService::new(
+            "stockfish-play",
+            "Stockfish Play",
+            "for playing against the computer",
+        )
+        .profile("stockfish-play")
+        .repo("lichess-org", "lila-fishnet")
+        .resources(2560, 1500, 2000),
+        OptionalService::new(
+            "stockfish-analysis",
+            "Stockfish Game Analysis",
+            "for requesting computer analysis of games",
+        )
+        .profile("stockfish-analysis")
+        .requires(
+            Target::Profile("base"),
+            "the analysis fishnet client fetches work from the fishnet endpoint on lila (http://lila:9663)",
+        )
+        .resources(512, 1000, 500),
+        OptionalService::new(
+            "external-engine",
+            "External Engine",
+            "for connecting a local chess engine to the analysis board",
+        )
+        .profile("external-engine")
+        .repo("lichess-org", "lila-engine")
+        .port(Port::new(9666, "lila-engine (External engine)"))
+        .resources(1024, 1000, 2000),
+        OptionalService::new("search", "Search", "for searching games, forum posts, etc")
+            .profile("search")
+            .requires(
+                Target::Profile("base"),
+                "the search ingestor reads from the mongodb secondary",
+            )
+            .requires(
+                Target::Setting(Setting::SetupDatabase),
+                "the search index is only filled by the utils ingestor when the database is seeded",
+            )
+            .port(
+                Port::new(9200, "Elasticsearch")
+                    .health("/_cluster/health?wait_for_status=yellow&timeout=1s"),
+            )
+            .port(Port::new(8092, "Elasticvue (Elasticsearch explorer)").health("/"))
+            .resources(2048, 1000, 2000),
+        OptionalService::new(
+            "gifs",
+            "GIF + image generation",
+            "for generating animated GIFs and screenshots of games",
+        )
+        .profile("gifs")
+        .repo("lichess-org", "lila-gif")
+        .port(
+            Port::new(6175, "lila-gif")
+                .page("/image.gif?fen=4k3/6KP/8/8/8/8/7p/8")
+                .health("/image.gif?fen=4k3/6KP/8/8/8/8/7p/8"),
+        )
+        .resources(1024, 1000, 2000),
+        OptionalService::new("push", "Push server", "for Lichess notifications")
+            .profile("push")
+            .repo("lichess-org", "lila-push")
+            .port(Port::new(9054, "lila-push"))
+            .resources(1024, 1000, 2000),
+        OptionalService::new(
+            "thumbnails",
+            "Image uploads + thumbnails",
+            "for blog/coach/streamer images",
+        )
+        .profile("thumbnails")
+        .port(
+            Port::new(3001, "Picfit (Image resizing)")
+                .page("/healthcheck")
+                .health("/healthcheck"),
+        )
+        .resources(128, 100, 200),
+        OptionalService::new("api-docs", "API docs", "standalone API documentation")
+            .profile("api-docs")
+            .repo("lichess-org", "api")
+            .port(Port::new(8089, "API docs").health("/"))
+            .resources(512, 500, 800),
+        OptionalService::new("chessground", "Chessground", "standalone board UI")
+            .profile("chessground")
+            .repo("lichess-org", "chessground")
+            .port(Port::new(8090, "Chessground").page("/demo.html").health("/"))
+            .resources(512, 500, 800),
+        OptionalService::new("pgn-viewer", "PGN Viewer", "standalone PGN viewer")
+            .profile("pgn-viewer")
+            .repo("lichess-org", "pgn-viewer")
+            .port(Port::new(8091, "pgn-viewer").health("/"))
+            .requires(
+                Target::Service("chessground"),
+                "pgn-viewer draws its board with chessground, so changes to it are made against a local chessground",
+            )
+            .resources(512, 500, 800),
+        OptionalService::new("scalachess", "Scalachess", "standalone chess logic library")
+            .repo("lichess-org", "scalachess")
+            .resources(0, 0, 200),
+        OptionalService::new("berserk", "Berserk", "Python API client")
+            .repo("lichess-org", "berserk")
+            .resources(0, 0, 50),
+        OptionalService::new("monitoring", "Monitoring", "Metric collection using InfluxDB")
+            .profile("monitoring")
+            .port(
+                Port::new(8086, "InfluxDB Metrics")
+                    .health("/health")
+                    .credentials("u: admin / p: password"),
+            )
+            .port(Port::new(9090, "Prometheus").health("/-/ready"))
+            .implies(Implies::Monitoring)
+            .resources(512, 500, 1000),
+        OptionalService::new("swiss-pairings", "Swiss Pairings", "bbpPairings tool")
+            .profile("swiss-pairings")
+            .repo("cyanfish", "bbpPairings")
+            .implies(Implies::BbpPairings)
+            .resources(256, 500, 200),
+    ]
+}
+
+/// Repositories needed by an Advanced setup, regardless of the selected services.
+/// The database seeder is only needed when the database is being seeded.
+pub fn core_repositories(setup_database: bool) -> Vec<Repository> {
+    let mut repos = vec![Repository::new("lichess-org", "lila")];
+    if setup_database {
+        repos.push(Repository::new("lichess-org", "lila-db-seed"));
+    }
+    repos
+}
+
+/// Compose profiles whose services use files kept in Git LFS, as (profile, service, what is
+/// missing from a clone made without it). Clones never download LFS files, see `./lila-docker lfs`.
+const LFS_CONTENT: [(&str, &str, &str); 1] = [(
+    "base",
+    "lila",
+    "large static assets (images, sounds) in the public/lifat submodule",
+)];
+
+/// The services among `profiles` that need Git LFS content, as (service, what is stored there)
+pub fn lfs_content(profiles: &[String]) -> Vec<(&'static str, &'static str)> {
+    LFS_CONTENT
+        .into_iter()
+        .filter(|(profile, _, _)| profiles.iter().any(|p| p == profile))
+        .map(|(_, service, content)| (service, content))
+        .collect()
+}
+
+/// Every repository lila-docker may mount into a container.
+pub fn all_repositories() -> Vec<Repository> {
+    let mut repos = core_repositories(true);
+    for repo in catalog()
+        .into_iter()
+        .flat_map(|service| service.repositories)
+    {
+        if !repos.contains(&repo) {
+            repos.push(repo);
+        }
+    }
+    repos
+}
+
+/// Set the `Config` fields that are derived from the selected services.
+pub fn apply_implications(config: &mut Config, services: &[OptionalService]) {
+    let implied = |implies| {
+        services
+            .iter()
+            .any(|service| service.has_implication(implies))
+    };
+
+    config.setup_bbppairings = Some(implied(Implies::BbpPairings));
+    config.mock_email = Some(!implied(Implies::RealEmail));
+    config.enable_monitoring = Some(implied(Implies::Monitoring));
+    config.lila_ws_container = Some(
+        if implied(Implies::LilaWsBuild) {
+            "build"
+        } else {
+            "image"
+        }
+        .to_string(),
+    );
+}
+
+/// The outcome of resolving the dependencies of the selected services.
+#[derive(Debug, Default)]
+pub struct Resolution {
+    /// The selected services plus everything they require, in catalog order
+    pub services: Vec<OptionalService>,
+    /// Services that were not selected but are required, with the explanation
+    pub added: Vec<(OptionalService, String)>,
+    /// Pairs of selected services that cannot run together, with the explanation
+    pub conflicts: Vec<String>,
+}
+
+/// Pull in every service required by `selected` and report incompatible choices.
+pub fn resolve(catalog: &[OptionalService], selected: &[OptionalService]) -> Resolution {
+    let mut ids: Vec<&str> = selected.iter().map(|service| service.id).collect();
+    let mut added = vec![];
+
+    let mut queue = selected.to_vec();
+    while let Some(service) = queue.pop() {
+        for relation in &service.requires {
+            let Target::Service(id) = relation.target else {
+                continue;
+            };
+            if ids.contains(&id) {
+                continue;
+            }
+            if let Some(required) = catalog.iter().find(|s| s.id == id) {
+                ids.push(required.id);
+                added.push((
+                    required.clone(),
+                    format!(
+                        "{} is required by {}: {}",
+                        required.label, service.label, relation.reason
+                    ),
+                ));
+                queue.push(required.clone());
+            }
+        }
+    }
+
+    let services: Vec<OptionalService> = catalog
+        .iter()
+        .filter(|service| ids.contains(&service.id))
+        .cloned()
+        .collect();
+
+    let conflicts = services
+        .iter()
+        .flat_map(|service| {
+            service
+                .conflicts
+                .iter()
+                .filter_map(|relation| match relation.target {
+                    Target::Service(id) => services.iter().find(|s| s.id == id).map(|other| {
+                        format!(
+                            "{} cannot be used together with {}: {}",
+                            service.label, other.label, relation.reason
+                        )
+                    }),
+                    _ => None,
+                })
+        })
+        .collect();
+
+    Resolution {
+        services,
+        added,
+        conflicts,
+    }
+}
+
+/// Explain each setup option that a selected service needs but that was not chosen.
+pub fn missing_settings(services: &[OptionalService], settings: &[Setting]) -> Vec<String> {
+    services
+        .iter()
+        .flat_map(|service| {
+            service
+                .requires
+                .iter()
+                .filter_map(move |relation| match &relation.target {
+                    Target::Setting(setting) if !settings.contains(setting) => Some(format!(
+                        "{} works best with {} enabled: {}",
+                        service.label,
+                        setting.label(),
+                        relation.reason
+                    )),
+                    _ => None,
+                })
+        })
+        .collect()
+}
+
+/// Profiles that are not tied to an optional service.
+const CORE_PROFILES: [&str; 2] = ["base", "quick"];
+
+/// Check a final `compose_profiles` list: every profile must exist, exactly one
+/// of `base` or `quick` must be present, and every service's profile
+/// requirements and conflicts must be satisfied.
+pub fn validate_profiles(profiles: &[String]) -> Result<(), Vec<String>> {
+    let catalog = catalog();
+    let has = |profile: &str| profiles.iter().any(|p| p == profile);
+    let mut errors = vec![];
+
+    for profile in profiles {
+        let known = CORE_PROFILES.contains(&profile.as_str())
+            || catalog
+                .iter()
+                .any(|service| service.compose_profiles.contains(&profile.as_str()));
+        if !known {
+            errors.push(format!("`{profile}` is not a known compose profile"));
+        }
+    }
+
+    match (has("base"), has("quick")) {
+        (true, true) => {
+            errors.push("`base` and `quick` profiles cannot be combined".to_string());
+        }
+        (false, false) => {
+            errors.push("one of the `base` or `quick` profiles is required".to_string());
+        }
+        _ => {}
+    }
+
+    for service in catalog
+        .iter()
+        .filter(|service| service.is_enabled_by(profiles))
+    {
+        for relation in &service.requires {
+            let missing = match relation.target {
+                Target::Profile(profile) => {
+                    (!has(profile)).then(|| format!("the `{profile}` profile"))
+                }
+                Target::Service(id) => catalog
+                    .iter()
+                    .find(|s| s.id == id)
+                    .filter(|required| !required.is_enabled_by(profiles))
+                    .map(|required| required.label.to_string()),
+                Target::Setting(_) => None,
+            };
+            if let Some(missing) = missing {
+                errors.push(format!(
+                    "{} requires {missing}: {}",
+                    service.label, relation.reason
+                ));
+            }
+        }
+        for relation in &service.conflicts {
+            let present = match relation.target {
+                Target::Profile(profile) => {
+                    has(profile).then(|| format!("the `{profile}` profile"))
+                }
+                Target::Service(id) => catalog
+                    .iter()
+                    .find(|s| s.id == id)
+                    .filter(|other| other.is_enabled_by(profiles))
+                    .map(|other| other.label.to_string()),
+                Target::Setting(_) => None,
+            };
+            if let Some(present) = present {
+                errors.push(format!(
+                    "{} cannot be used with {present}: {}",
+                    service.label, relation.reason
+                ));
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// The optional services that are part of a setup running `profiles`.
+pub fn enabled_services(profiles: &[String]) -> Vec<OptionalService> {
+    catalog()
+        .into_iter()
+        .filter(|service| service.is_enabled_by(profiles))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalog_ids_are_unique() {
+        let mut ids: Vec<&str> = catalog().iter().map(|service| service.id).collect();
+        let count = ids.len();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), count);
+    }
+
+    #[test]
+    fn test_catalog_ports_are_unique() {
+        let mut ports: Vec<u16> = catalog()
+            .iter()
+            .flat_map(|service| service.ports.iter().map(|port| port.number))
+            .collect();
+        let count = ports.len();
+        ports.sort_unstable();
+        ports.dedup();
+        assert_eq!(ports.len(), count);
+    }
+
+    #[test]
+    fn test_all_repositories() {
+        let projects: Vec<String> = all_repositories()
+            .into_iter()
+            .map(|repo| repo.project)
+            .collect();
+        assert_eq!(
+            projects,
+            [
+                "lila",
+                "lila-db-seed",
+                "lila-ws",
+                "lila-fishnet",
+                "lila-engine",
+                "lila-gif",
+                "lila-push",
+                "api",
+                "chessground",
+                "pgn-viewer",
+                "scalachess",
+                "berserk",
+                "bbpPairings",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lfs_content() {
+        let profiles: Vec<&str> = catalog()
+            .iter()
+            .flat_map(|service| service.compose_profiles.clone())
+            .chain(CORE_PROFILES)
+            .collect();
+        assert!(LFS_CONTENT
+            .iter()
+            .all(|(profile, _, _)| profiles.contains(profile)));
+
+        let lfs_content = |profiles: &[&str]| {
+            lfs_content(&profiles.iter().map(ToString::to_string).collect::<Vec<_>>())
+        };
+        assert_eq!(lfs_content(&["base", "email"])[0].0, "lila");
+        assert!(lfs_content(&["email", "gifs"]).is_empty());
+        assert!(lfs_content(&["quick"]).is_empty());
+    }
+
+    #[test]
+    fn test_apply_implications() {
+        let services: Vec<OptionalService> = catalog()
+            .into_iter()
+            .filter(|service| ["email", "lila-ws-build"].contains(&service.id))
+            .collect();
+
+        let mut config = Config::default();
+        apply_implications(&mut config, &services);
+
+        assert_eq!(config.setup_bbppairings, Some(false));
+        assert_eq!(config.mock_email, Some(false));
+        assert_eq!(config.enable_monitoring, Some(false));
+        assert_eq!(config.lila_ws_container.as_deref(), Some("build"));
+
+        apply_implications(&mut config, &[]);
+
+        assert_eq!(config.mock_email, Some(true));
+        assert_eq!(config.lila_ws_container.as_deref(), Some("image"));
+    }
+
+    fn test_service(id: &'static str) -> OptionalService {
+        OptionalService::new(id, id, "").profile(id)
+    }
+
+    #[test]
+    fn test_resolve_pulls_in_requirements() {
+        let catalog = vec![
+            test_service("a").requires(Target::Service("b"), "a talks to b"),
+            test_service("b").requires(Target::Service("c"), "b talks to c"),
+            test_service("c"),
+            test_service("d"),
+        ];
+
+        let resolution = resolve(&catalog, &[catalog[0].clone()]);
+
+        assert_eq!(
+            resolution
+                .services
+                .iter()
+                .map(|service| service.id)
+                .collect::<Vec<_>>(),
+            ["a", "b", "c"]
+        );
+        assert_eq!(
+            resolution
+                .added
+                .iter()
+                .map(|(_, reason)| reason.as_str())
+                .collect::<Vec<_>>(),
+            [
+                "b is required by a: a talks to b",
+                "c is required by b: b talks to c"
+            ]
+        );
+        assert!(resolution.conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_resolve_catalog() {
+        let catalog = catalog();
+        let selected: Vec<OptionalService> = catalog
+            .iter()
+            .filter(|service| service.id == "pgn-viewer")
+            .cloned()
+            .collect();
+
+        let resolution = resolve(&catalog, &selected);
+
+        assert_eq!(
+            resolution
+                .services
+                .iter()
+                .map(|service| service.id)
+                .collect::<Vec<_>>(),
+            ["chessground", "pgn-viewer"]
+        );
+        assert_eq!(resolution.added.len(), 1);
+        assert!(resolution.added[0]
+            .1
+            .starts_with("Chessground is required by PGN Viewer: "));
+    }
+
+    #[test]
+    fn test_resolve_reports_conflicts() {
+        let catalog = vec![
+            test_service("a").conflicts(Target::Service("b"), "both bind the same port"),
+            test_service("b"),
+        ];
+
+        let resolution = resolve(&catalog, &catalog);
+
+        assert_eq!(
+            resolution.conflicts,
+            ["a cannot be used together with b: both bind the same port"]
+        );
+    }
+
+    #[test]
+    fn test_missing_settings() {
+        let services: Vec<OptionalService> = catalog()
+            .into_iter()
+            .filter(|service| service.id == "search")
+            .collect();
+
+        assert_eq!(
+            missing_settings(&services, &[Setting::SetupDatabase]).len(),
+            0
+        );
+        assert_eq!(missing_settings(&services, &[]).len(), 1);
+    }
+
+    #[test]
+    fn test_validate_profiles() {
+        let profiles =
+            |list: &[&str]| -> Vec<String> { list.iter().map(ToString::to_string).collect() };
+
+        assert!(validate_profiles(&profiles(&["base", "search", "stockfish-analysis"])).is_ok());
+        assert!(validate_profiles(&profiles(&["quick"])).is_ok());
+
+        assert_eq!(
+            validate_profiles(&profiles(&["base", "serach"])),
+            Err(vec!["`serach` is not a known compose profile".to_string()])
+        );
+        assert_eq!(
+            validate_profiles(&profiles(&["base", "quick"])).unwrap_err()[0],
+            "`base` and `quick` profiles cannot be combined"
+        );
+        assert_eq!(
+            validate_profiles(&profiles(&["email"])).unwrap_err()[0],
+            "one of the `base` or `quick` profiles is required"
+        );
+
+        let errors =
+            validate_profiles(&profiles(&["quick", "lila-ws-build", "stockfish-analysis"]))
+                .unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(
+            errors[0].starts_with("Websocket source code cannot be used with the `quick` profile")
+        );
+        assert!(errors[1].starts_with("Stockfish Game Analysis requires the `base` profile"));
+    }
+
+    #[test]
+    fn test_enabled_services() {
+        let profiles = vec![
+            "base".to_string(),
+            "email".to_string(),
+            "monitoring".to_string(),
+        ];
+        let ids: Vec<&str> = enabled_services(&profiles)
+            .iter()
+            .map(|service| service.id)
+            .collect();
+        assert_eq!(ids, ["email", "monitoring"]);
+    }
+}
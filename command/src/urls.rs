@@ -0,0 +1,150 @@
+use serde::Serialize;
+
+use crate::{compose::PublishedPort, config::DEFAULT_LILA_PORT, services, Config};
+
+/// Where to open one of the running services
+#[derive(Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct ServiceUrl {
+    pub label: String,
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credentials: Option<String>,
+    /// The port in the compose files, if `settings.toml` publishes the service on another one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub moved_from: Option<u16>,
+}
+
+/// The host the services are reached on: the one chosen with `./lila-docker hostname`,
+/// unless lila is served through a proxy, which the other services are not
+fn hostname(config: &Config) -> &str {
+    let proxied = config
+        .lila_url
+        .as_deref()
+        .is_some_and(|url| !url.starts_with("http://"));
+    config
+        .lila_domain
+        .as_deref()
+        .filter(|_| !proxied)
+        .map_or("localhost", |domain| {
+            domain.rsplit_once(':').map_or(domain, |(host, _)| host)
+        })
+}
+
+/// lila, then every port of the services that `compose_profiles` enable, on the host ports
+/// they are published on
+pub fn directory(config: &Config, published: &[PublishedPort]) -> Vec<ServiceUrl> {
+    let host = hostname(config);
+    let mut urls = vec![ServiceUrl {
+        label: "Lichess".to_string(),
+        url: config
+            .lila_url
+            .clone()
+            .unwrap_or_else(|| format!("http://localhost:{DEFAULT_LILA_PORT}")),
+        credentials: None,
+        moved_from: None,
+    }];
+    let profiles = config.compose_profiles.clone().unwrap_or_default();
+    for service in services::enabled_services(&profiles) {
+        for port in &service.ports {
+            let number = published
+                .iter()
+                .find(|published| published.default_host == port.number)
+                .map_or(port.number, |published| published.host);
+            urls.push(ServiceUrl {
+                label: port.label.to_string(),
+                url: format!("http://{host}:{number}{}", port.page),
+                credentials: port.credentials.map(ToString::to_string),
+                moved_from: (number != port.number).then_some(port.number),
+            });
+        }
+    }
+    urls
+}
+
+/// One aligned line per URL
+pub fn table(urls: &[ServiceUrl]) -> String {
+    let width = urls.iter().map(|url| url.label.len()).max().unwrap_or(0);
+    urls.iter()
+        .map(|url| {
+            let mut line = vec![format!("{:width$}  {}", url.label, url.url)];
+            line.extend(
+                url.credentials
+                    .as_ref()
+                    .map(|credentials| format!("({credentials})")),
+            );
+            line.extend(
+                url.moved_from
+                    .map(|port| format!("(moved from port {port})")),
+            );
+            line.join(" ")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directory() {
+        let config = Config {
+            compose_profiles: Some(vec!["monitoring".to_string(), "gifs".to_string()]),
+            lila_domain: Some("192.168.1.2:8080".to_string()),
+            lila_url: Some("http://192.168.1.2:8080".to_string()),
+            ..Default::default()
+        };
+        let published = [PublishedPort {
+            service: "influxdb".to_string(),
+            container: "8086".to_string(),
+            host: 8087,
+            default_host: 8086,
+        }];
+
+        let urls = directory(&config, &published);
+        assert_eq!(
+            table(&urls),
+            [
+                "Lichess           http://192.168.1.2:8080",
+                "lila-gif          http://192.168.1.2:6175/image.gif?fen=4k3/6KP/8/8/8/8/7p/8",
+                "InfluxDB Metrics  http://192.168.1.2:8087/ (u: admin / p: password) (moved from port 8086)",
+                "Prometheus        http://192.168.1.2:9090/",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_directory_proxied_lila() {
+        let config = Config {
+            compose_profiles: Some(vec!["email".to_string()]),
+            lila_domain: Some("example-8080.app.github.dev".to_string()),
+            lila_url: Some("https://example-8080.app.github.dev".to_string()),
+            ..Default::default()
+        };
+        let urls: Vec<String> = directory(&config, &[])
+            .into_iter()
+            .map(|url| url.url)
+            .collect();
+        assert_eq!(
+            urls,
+            [
+                "https://example-8080.app.github.dev",
+                "http://localhost:8025/"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_directory_defaults() {
+        assert_eq!(
+            directory(&Config::default(), &[]),
+            [ServiceUrl {
+                label: "Lichess".to_string(),
+                url: "http://localhost:8080".to_string(),
+                credentials: None,
+                moved_from: None,
+            }]
+        );
+    }
+}